@@ -0,0 +1,680 @@
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::time::{Instant, Sleep};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use crate::{CloseCode, Error, Fragment, Message, MessageCodec, Opcode, Result};
+
+const DEFAULT_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The stage of the WebSocket closing handshake that a connection has reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseState {
+	/// Messages can be sent and received.
+	Open,
+	/// A close frame has been sent and the client is waiting for the server to reply with its own.
+	/// Messages can still be received but no more can be sent.
+	Closing,
+	/// Close frames have been exchanged in both directions. No more messages can be sent.
+	Closed,
+}
+
+/// Exposes a `Sink` and a `Stream` for sending and receiving WebSocket messages asynchronously.
+///
+/// The client takes part in the closing handshake on its own: a close frame from the server is echoed back
+/// automatically, and sending is refused with [`Error::ConnectionClosed`] once either side has sent a close frame.
+/// Use [`close`](Self::close) to start the closing handshake and shut down the connection.
+///
+/// Besides whole messages, [`send_fragment`](Self::send_fragment) sends a message piece by piece. The client can also answer
+/// pings and send keepalive pings of its own; see [`set_auto_pong`](Self::set_auto_pong) and
+/// [`set_keepalive`](Self::set_keepalive).
+pub struct AsyncClient<S> {
+	framed: Framed<S, ClientCodec>,
+	state: CloseState,
+	replies: VecDeque<Message>,
+	close_timeout: Duration,
+	auto_pong: bool,
+	keepalive: Option<Keepalive>,
+	timed_out: bool,
+}
+
+/// Decodes either whole messages or single fragments, depending on what the caller of the client asked for.
+struct ClientCodec {
+	inner: MessageCodec,
+	fragments: bool,
+}
+
+enum Received {
+	Message(Message),
+	Fragment(Fragment),
+}
+
+impl Received {
+	fn opcode(&self) -> Opcode {
+		match self {
+			Self::Message(message) => message.opcode(),
+			Self::Fragment(fragment) => fragment.opcode(),
+		}
+	}
+}
+
+impl Decoder for ClientCodec {
+	type Item = Received;
+	type Error = Error;
+
+	fn decode(
+		&mut self,
+		src: &mut BytesMut,
+	) -> Result<Option<Received>> {
+		if self.fragments {
+			Ok(self
+				.inner
+				.decode_fragment(src)?
+				.map(Received::Fragment))
+		} else {
+			Ok(self.inner.decode(src)?.map(Received::Message))
+		}
+	}
+}
+
+impl<I> Encoder<I> for ClientCodec
+where
+	MessageCodec: Encoder<I, Error = Error>,
+{
+	type Error = Error;
+
+	fn encode(
+		&mut self,
+		item: I,
+		dst: &mut BytesMut,
+	) -> Result<()> {
+		self.inner.encode(item, dst)
+	}
+}
+
+async fn read_chunk<R: AsyncRead + Unpin>(
+	reader: &mut R,
+	chunk_size: usize,
+) -> io::Result<Bytes> {
+	let mut buf = BytesMut::with_capacity(chunk_size);
+	while buf.len() < chunk_size {
+		let remaining = chunk_size - buf.len();
+		if reader
+			.read_buf(&mut (&mut buf).limit(remaining))
+			.await? == 0
+		{
+			break;
+		}
+	}
+
+	Ok(buf.freeze())
+}
+
+/// Sends a ping once the connection has been idle for a while, and gives up if nothing comes back in time.
+struct Keepalive {
+	interval: Duration,
+	timeout: Duration,
+	timer: Pin<Box<Sleep>>,
+	last_activity: Instant,
+	ping_sent: Option<Instant>,
+	round_trip_time: Option<Duration>,
+}
+
+impl Keepalive {
+	fn new(
+		interval: Duration,
+		timeout: Duration,
+	) -> Self {
+		let now = Instant::now();
+		Self {
+			interval,
+			timeout,
+			timer: Box::pin(tokio::time::sleep_until(
+				now + interval,
+			)),
+			last_activity: now,
+			ping_sent: None,
+			round_trip_time: None,
+		}
+	}
+
+	fn receive(
+		&mut self,
+		opcode: Opcode,
+	) {
+		let now = Instant::now();
+		if let Some(ping_sent) = self.ping_sent.take() {
+			if opcode == Opcode::Pong {
+				self.round_trip_time = Some(now - ping_sent);
+			}
+		}
+
+		self.last_activity = now;
+	}
+
+	/// Returns a ping once it's time to send one, or an error once the server has failed to respond to the last one.
+	fn poll(
+		&mut self,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<Message>> {
+		loop {
+			ready!(self.timer.as_mut().poll(cx));
+
+			let now = Instant::now();
+			if let Some(ping_sent) = self.ping_sent {
+				if now >= ping_sent + self.timeout {
+					return Poll::Ready(Err(Error::HeartbeatTimeout));
+				}
+
+				self.timer
+					.as_mut()
+					.reset(ping_sent + self.timeout);
+			} else if now >= self.last_activity + self.interval {
+				self.ping_sent = Some(now);
+				self.timer.as_mut().reset(now + self.timeout);
+				return Poll::Ready(Ok(Message::ping(Vec::new())));
+			} else {
+				self.timer
+					.as_mut()
+					.reset(self.last_activity + self.interval);
+			}
+		}
+	}
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncClient<S> {
+	/// Wraps a stream that has completed the opening handshake and is framed with a [`MessageCodec`].
+	#[must_use]
+	pub fn new(framed: Framed<S, MessageCodec>) -> Self {
+		Self {
+			framed: framed.map_codec(|inner| ClientCodec {
+				inner,
+				fragments: false,
+			}),
+			state: CloseState::Open,
+			replies: VecDeque::new(),
+			close_timeout: DEFAULT_CLOSE_TIMEOUT,
+			auto_pong: false,
+			keepalive: None,
+			timed_out: false,
+		}
+	}
+
+	/// Sets how long [`close`](Self::close) waits for the server's close frame before shutting down the stream.
+	/// The default is 5 seconds.
+	pub fn set_close_timeout(
+		&mut self,
+		timeout: Duration,
+	) {
+		self.close_timeout = timeout;
+	}
+
+	/// Sets whether pings from the server are answered automatically with a pong carrying the same payload.
+	///
+	/// Pings are still returned from the stream. Pongs are sent whenever either half of the client is polled, so
+	/// this works after the client has been split and the halves have been moved to different tasks, as long as the
+	/// stream half keeps being polled. If several pings arrive before a pong can be sent, only the most recent one is
+	/// answered. The default is `false`.
+	pub fn set_auto_pong(
+		&mut self,
+		value: bool,
+	) {
+		self.auto_pong = value;
+	}
+
+	/// Sends a ping whenever nothing has been received from the server for `interval`, and fails the connection if
+	/// nothing arrives within `timeout` of sending the ping.
+	///
+	/// A pong or any other message counts as a response. When the timeout expires, the stream yields
+	/// [`Error::HeartbeatTimeout`] and then ends. The heartbeat runs while the stream half of the client is being
+	/// polled, which is normally the case while waiting for the next message.
+	///
+	/// # Panics
+	///
+	/// This method panics if it's called outside of a Tokio runtime with the time driver enabled.
+	pub fn set_keepalive(
+		&mut self,
+		interval: Duration,
+		timeout: Duration,
+	) {
+		self.keepalive = Some(Keepalive::new(
+			interval, timeout,
+		));
+	}
+
+	/// Returns the time it took the server to answer the most recent keepalive ping, if any has been answered yet.
+	#[must_use]
+	pub fn round_trip_time(&self) -> Option<Duration> {
+		self.keepalive
+			.as_ref()
+			.and_then(|keepalive| keepalive.round_trip_time)
+	}
+
+	/// Returns the stage of the closing handshake that the connection has reached.
+	#[must_use]
+	pub fn state(&self) -> CloseState {
+		self.state
+	}
+
+	/// Returns a reference to the underlying stream.
+	#[must_use]
+	pub fn get_ref(&self) -> &S {
+		self.framed.get_ref()
+	}
+
+	/// Returns a mutable reference to the underlying stream.
+	///
+	/// Reading from or writing to the stream directly is likely to corrupt the WebSocket connection.
+	pub fn get_mut(&mut self) -> &mut S {
+		self.framed.get_mut()
+	}
+
+	/// Returns a reference to the codec, for instance to look up the negotiated subprotocol.
+	#[must_use]
+	pub fn codec(&self) -> &MessageCodec {
+		&self.framed.codec().inner
+	}
+
+	/// Consumes the client, returning the framed stream.
+	///
+	/// Any close frame that has been queued but not yet sent is discarded.
+	#[must_use]
+	pub fn into_inner(self) -> Framed<S, MessageCodec> {
+		self.framed.map_codec(|codec| codec.inner)
+	}
+
+	/// Starts the closing handshake, waits for the server's close frame and shuts down the stream.
+	///
+	/// Messages that arrive while waiting for the server's reply are discarded. If the server doesn't reply within
+	/// the close timeout, the stream is shut down anyway. If the server has already sent a close frame, this method
+	/// only flushes the reply and shuts down the stream.
+	///
+	/// # Errors
+	///
+	/// This method returns an `Err` result if writing to or shutting down the stream fails.
+	pub async fn close(
+		&mut self,
+		code: CloseCode,
+		reason: &str,
+	) -> Result<()> {
+		if self.state == CloseState::Open {
+			self.send(Message::close_with_reason(
+				code,
+				reason.to_owned(),
+			))
+			.await?;
+		}
+
+		let close_timeout = self.close_timeout;
+		let _ = tokio::time::timeout(close_timeout, async {
+			while self.state != CloseState::Closed {
+				match self.next().await {
+					Some(Ok(_)) => {}
+					Some(Err(err)) => return Err(err),
+					None => break,
+				}
+			}
+
+			Ok(())
+		})
+		.await;
+
+		SinkExt::close(self).await
+	}
+
+	/// Sends one fragment of a message and flushes it to the stream.
+	///
+	/// The fragments of a message must be sent in order, starting with one marked as first and ending with one
+	/// marked as last, and no other text or binary message may be sent in between. Control frames, including
+	/// automatic pongs, may be interleaved with the fragments.
+	///
+	/// # Errors
+	///
+	/// This method returns an `Err` result if the connection is closing, if writing to the stream fails, or with an
+	/// [`io::ErrorKind::InvalidInput`] error if a control frame is split into several fragments.
+	pub async fn send_fragment(
+		&mut self,
+		fragment: Fragment,
+	) -> Result<()> {
+		poll_fn(|cx| Sink::<Message>::poll_ready(Pin::new(&mut *self), cx)).await?;
+		if fragment.opcode() == Opcode::Close {
+			self.state = CloseState::Closing;
+		}
+
+		Pin::new(&mut self.framed).start_send(fragment)?;
+		poll_fn(|cx| Sink::<Message>::poll_flush(Pin::new(&mut *self), cx)).await
+	}
+
+	/// Sends everything read from `reader` as a single message, split into fragments of `chunk_size` bytes.
+	///
+	/// At most two chunks are held in memory at a time, so arbitrarily large sources can be sent. The data is not
+	/// validated, so the reader must produce valid UTF-8 if `opcode` is [`Opcode::Text`]. On success, returns the
+	/// number of bytes sent.
+	///
+	/// # Errors
+	///
+	/// This method returns an `Err` result if reading from `reader` fails or if sending a fragment fails, or with an
+	/// [`io::ErrorKind::InvalidInput`] error if `opcode` is a control opcode. If an error happens after the first fragment has been sent, the message is left
+	/// incomplete and the connection should be closed.
+	///
+	/// # Panics
+	///
+	/// This method panics if `chunk_size` is zero.
+	pub async fn send_reader<R: AsyncRead + Unpin>(
+		&mut self,
+		opcode: Opcode,
+		mut reader: R,
+		chunk_size: usize,
+	) -> Result<u64> {
+		assert!(
+			chunk_size > 0,
+			"chunk size must be greater than zero"
+		);
+		if opcode.is_control() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"control frames can't be sent from a reader",
+			)
+			.into());
+		}
+
+		let mut sent = 0;
+		let mut first = true;
+		let mut chunk = read_chunk(&mut reader, chunk_size).await?;
+		loop {
+			// A short chunk means the reader is exhausted; otherwise read ahead to find out whether this is the last one
+			let next = if chunk.len() == chunk_size {
+				read_chunk(&mut reader, chunk_size).await?
+			} else {
+				Bytes::new()
+			};
+
+			let last = next.is_empty();
+			sent += chunk.len() as u64;
+			self.send_fragment(Fragment::new(
+				opcode, chunk, first, last,
+			))
+			.await?;
+
+			if last {
+				return Ok(sent);
+			}
+
+			first = false;
+			chunk = next;
+		}
+	}
+
+	/// Receives the next frame without reassembling fragmented messages.
+	///
+	/// This avoids buffering large messages: each fragment is returned as soon as it arrives, tagged with the opcode
+	/// of its message and whether it's the first or last fragment. Control frames are returned as fragments that are
+	/// both first and last, and take part in the closing handshake and keepalive as usual. See
+	/// [`MessageCodec::decode_fragment`] for the checks that are made on each fragment.
+	///
+	/// Calls to this method shouldn't be mixed with receiving whole messages in the middle of a fragmented message.
+	pub async fn next_fragment(&mut self) -> Option<Result<Fragment>> {
+		poll_fn(|cx| {
+			self.poll_receive(cx, true)
+				.map_ok(|received| match received {
+					Received::Fragment(fragment) => fragment,
+					Received::Message(_) => unreachable!("messages are only decoded on request"),
+				})
+		})
+		.await
+	}
+
+	fn poll_receive(
+		&mut self,
+		cx: &mut Context<'_>,
+		fragments: bool,
+	) -> Poll<Option<Result<Received>>> {
+		if self.timed_out {
+			return Poll::Ready(None);
+		}
+
+		if let Some(keepalive) = self
+			.keepalive
+			.as_mut()
+			.filter(|_| self.state == CloseState::Open)
+		{
+			if let Poll::Ready(ping) = keepalive.poll(cx) {
+				match ping {
+					Ok(ping) => self.replies.push_back(ping),
+					Err(err) => {
+						self.timed_out = true;
+						return Poll::Ready(Some(Err(err)));
+					}
+				}
+			}
+		}
+
+		// Send any replies while we wait for incoming data, but don't let a slow writer hold up the reader
+		if let Poll::Ready(Err(err)) = self.poll_replies(cx) {
+			return Poll::Ready(Some(Err(err)));
+		}
+
+		self.framed.codec_mut().fragments = fragments;
+		loop {
+			let received = match ready!(self.framed.poll_next_unpin(cx)) {
+				Some(Ok(received)) => received,
+				other => return Poll::Ready(other),
+			};
+
+			if self.state == CloseState::Closed {
+				// Nothing is meaningful after the closing handshake; wait for the server to close the stream
+				continue;
+			}
+
+			if let Some(keepalive) = &mut self.keepalive {
+				keepalive.receive(received.opcode());
+			}
+
+			match &received {
+				Received::Message(message) => self.receive(message),
+				Received::Fragment(fragment) => {
+					if fragment.opcode().is_control() {
+						if let Ok(message) = Message::new(
+							fragment.opcode(),
+							fragment.data().clone(),
+						) {
+							self.receive(&message);
+						}
+					}
+				}
+			}
+
+			if let Poll::Ready(Err(err)) = self.poll_replies(cx) {
+				return Poll::Ready(Some(Err(err)));
+			}
+
+			return Poll::Ready(Some(Ok(received)));
+		}
+	}
+
+	fn framed_sink(&mut self) -> Pin<&mut impl Sink<Message, Error = Error>> {
+		Pin::new(&mut self.framed)
+	}
+
+	fn receive(
+		&mut self,
+		message: &Message,
+	) {
+		match message.opcode() {
+			Opcode::Close => {}
+			Opcode::Ping if self.auto_pong && self.state == CloseState::Open => {
+				let pong = Message::pong(message.data().clone());
+
+				// RFC 6455 section 5.5.3 lets us answer only the most recent ping, so a peer that floods pings while
+				// we can't write doesn't grow the queue
+				match self
+					.replies
+					.iter_mut()
+					.find(|reply| reply.opcode() == Opcode::Pong)
+				{
+					Some(pending) => *pending = pong,
+					None => self.replies.push_back(pong),
+				}
+
+				return;
+			}
+			_ => return,
+		}
+
+		if self.state == CloseState::Open {
+			// Echo the status code, as suggested by RFC 6455 section 5.5.1
+			let reply = match message.as_close() {
+				Some(frame) => Message::close_with_reason(frame.code(), String::new()),
+				None => Message::close(),
+			};
+
+			self.replies.push_back(reply);
+		}
+
+		self.state = CloseState::Closed;
+	}
+
+	fn poll_replies(
+		&mut self,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<()>> {
+		if self.replies.is_empty() {
+			return Poll::Ready(Ok(()));
+		}
+
+		while let Some(reply) = self.replies.pop_front() {
+			if self.framed_sink().poll_ready(cx)?.is_pending() {
+				self.replies.push_front(reply);
+				return Poll::Pending;
+			}
+
+			self.framed_sink().start_send(reply)?;
+		}
+
+		self.framed_sink().poll_flush(cx)
+	}
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for AsyncClient<S> {
+	type Item = Result<Message>;
+
+	fn poll_next(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Message>>> {
+		self.get_mut()
+			.poll_receive(cx, false)
+			.map_ok(|received| match received {
+				Received::Message(message) => message,
+				Received::Fragment(_) => unreachable!("fragments are only decoded on request"),
+			})
+	}
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Message> for AsyncClient<S> {
+	type Error = Error;
+
+	fn poll_ready(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_replies(cx))?;
+		if this.state != CloseState::Open {
+			return Poll::Ready(Err(Error::ConnectionClosed));
+		}
+
+		this.framed_sink().poll_ready(cx)
+	}
+
+	fn start_send(
+		self: Pin<&mut Self>,
+		item: Message,
+	) -> Result<()> {
+		let this = self.get_mut();
+		if this.state != CloseState::Open {
+			return Err(Error::ConnectionClosed);
+		}
+
+		if item.opcode() == Opcode::Close {
+			this.state = CloseState::Closing;
+		}
+
+		this.framed_sink().start_send(item)
+	}
+
+	fn poll_flush(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_replies(cx))?;
+		this.framed_sink().poll_flush(cx)
+	}
+
+	fn poll_close(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_replies(cx))?;
+		this.framed_sink().poll_close(cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn answer_only_latest_ping() {
+		let (stream, _server) = tokio::io::duplex(64);
+		let mut client = AsyncClient::new(Framed::new(
+			stream,
+			MessageCodec::client(),
+		));
+		client.set_auto_pong(true);
+
+		for i in 0..100u8 {
+			client.receive(&Message::ping(vec![i]));
+		}
+
+		assert_eq!(client.replies.len(), 1);
+		assert_eq!(
+			client.replies[0].opcode(),
+			Opcode::Pong
+		);
+		assert_eq!(
+			client.replies[0].data().as_ref(),
+			[99]
+		);
+	}
+
+	#[tokio::test]
+	async fn send_reader_rejects_control_opcode() {
+		let (stream, _server) = tokio::io::duplex(64);
+		let mut client = AsyncClient::new(Framed::new(
+			stream,
+			MessageCodec::client(),
+		));
+		let err = client
+			.send_reader(Opcode::Ping, &b"ping"[..], 16)
+			.await
+			.unwrap_err();
+
+		assert!(matches!(&err, Error::Io(err) if err.kind() == io::ErrorKind::InvalidInput));
+		assert_eq!(
+			client.state(),
+			CloseState::Open
+		);
+	}
+}
@@ -1,15 +1,24 @@
-use std::net::SocketAddr;
-use std::{fmt, mem, result, str};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{io, mem, result, str};
 
 use base64::Engine;
+use bytes::BytesMut;
 use futures_util::StreamExt;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream as TokioTcpStream;
 use tokio_util::codec::{Decoder, Framed};
-use url::Url;
-use websocket_codec::UpgradeCodec;
+use url::{Host, Url};
+use websocket_codec::{PerMessageDeflate, UpgradeCodec, WebSocketExtension};
 
-use crate::{AsyncClient, AsyncConnector, AsyncMaybeTlsStream, Connector, MessageCodec, Result};
+use crate::connect::{connect_racing, interleave, with_timeout, SocketOptions, DEFAULT_CONNECT_ATTEMPT_DELAY};
+use crate::sync_client::read_into;
+use crate::{
+	AddressFamily, AsyncClient, AsyncConnector, AsyncMaybeTlsStream, Client, ConnectPhase, ConnectionInfo, Connector,
+	DeflateConfig, Error, MaybeTlsStream, MessageCodec, Resolver, Result, TokioResolver, UpgradeResponse,
+};
 
 fn replace_codec<T, C1, C2>(
 	framed: Framed<T, C1>,
@@ -26,17 +35,15 @@ where
 	Framed::from_parts(parts2)
 }
 
-macro_rules! writeok {
-    ($dst:expr, $($arg:tt)*) => {
-        let _ = fmt::Write::write_fmt(&mut $dst, format_args!($($arg)*));
-    }
-}
+fn resolve(url: &Url) -> Result<Vec<SocketAddr>> {
+	let addrs = url.socket_addrs(|| None)?;
+	if addrs.is_empty() {
+		return Err(Error::Url(format!(
+			"can't resolve host: {url}"
+		)));
+	}
 
-fn resolve(url: &Url) -> Result<SocketAddr> {
-	url.socket_addrs(|| None)?
-		.into_iter()
-		.next()
-		.ok_or_else(|| "can't resolve host".to_owned().into())
+	Ok(addrs)
 }
 
 fn make_key(
@@ -48,7 +55,7 @@ fn make_key(
 		24,
 		base64::engine::GeneralPurpose::encode_slice(
 			&base64::engine::general_purpose::STANDARD,
-			&key_bytes,
+			key_bytes,
 			key_base64
 		)
 		.unwrap()
@@ -57,9 +64,16 @@ fn make_key(
 	str::from_utf8(key_base64).unwrap()
 }
 
+fn is_protocol_header(name: &str) -> bool {
+	name.eq_ignore_ascii_case("Sec-WebSocket-Protocol")
+}
+
 fn build_request(
 	url: &Url,
+	host: Option<&str>,
 	key: &str,
+	subprotocols: &[String],
+	extensions: Option<&str>,
 	headers: &[(String, String)],
 ) -> String {
 	let mut s = String::new();
@@ -74,7 +88,9 @@ fn build_request(
 
 	s += " HTTP/1.1\r\n";
 
-	if let Some(host) = url.host() {
+	if let Some(host) = host {
+		writeok!(s, "Host: {host}\r\n");
+	} else if let Some(host) = url.host() {
 		writeok!(s, "Host: {host}", host = host);
 		if let Some(port) = url.port_or_known_default() {
 			writeok!(s, ":{port}", port = port);
@@ -92,7 +108,28 @@ fn build_request(
 		key = key
 	);
 
+	if !subprotocols.is_empty() {
+		writeok!(
+			s,
+			"Sec-WebSocket-Protocol: {subprotocols}\r\n",
+			subprotocols = subprotocols.join(", ")
+		);
+	}
+
+	if let Some(extensions) = extensions {
+		writeok!(
+			s,
+			"Sec-WebSocket-Extensions: {extensions}\r\n",
+			extensions = extensions
+		);
+	}
+
 	for (name, value) in headers {
+		// These have been merged into the list of subprotocols above
+		if is_protocol_header(name) {
+			continue;
+		}
+
 		writeok!(
 			s,
 			"{name}: {value}\r\n",
@@ -111,8 +148,22 @@ pub struct ClientBuilder {
 	url: Url,
 	connector: Option<Connector>,
 	async_connector: Option<AsyncConnector>,
+	tcp: TcpSettings,
+	tls_timeout: Option<Duration>,
+	upgrade_timeout: Option<Duration>,
+	server_name: Option<String>,
+	host: Option<String>,
 	key: Option<[u8; 16]>,
 	headers: Vec<(String, String)>,
+	deflate: Option<DeflateConfig>,
+	extensions: Vec<Box<dyn WebSocketExtension>>,
+	subprotocols: Vec<String>,
+	auto_pong: bool,
+	ping_interval: Option<Duration>,
+	pong_timeout: Option<Duration>,
+	max_frame_size: Option<usize>,
+	max_message_size: Option<usize>,
+	max_outgoing_frame_size: Option<usize>,
 }
 
 impl ClientBuilder {
@@ -132,8 +183,22 @@ impl ClientBuilder {
 			url,
 			connector: None,
 			async_connector: None,
+			tcp: TcpSettings::default(),
+			tls_timeout: None,
+			upgrade_timeout: None,
+			server_name: None,
+			host: None,
 			key: None,
 			headers: Vec::new(),
+			deflate: None,
+			extensions: Vec::new(),
+			subprotocols: Vec::new(),
+			auto_pong: false,
+			ping_interval: None,
+			pong_timeout: None,
+			max_frame_size: None,
+			max_message_size: None,
+			max_outgoing_frame_size: None,
 		}
 	}
 
@@ -143,10 +208,7 @@ impl ClientBuilder {
 		&mut self,
 		connector: Connector,
 	) -> Option<Connector> {
-		mem::replace(
-			&mut self.connector,
-			Some(connector),
-		)
+		self.connector.replace(connector)
 	}
 
 	/// Sets the SSL connector for the `async_connect` method.
@@ -155,13 +217,295 @@ impl ClientBuilder {
 		&mut self,
 		connector: AsyncConnector,
 	) -> Option<AsyncConnector> {
-		mem::replace(
-			&mut self.async_connector,
-			Some(connector),
-		)
+		self.async_connector.replace(connector)
+	}
+
+	/// Sets the address to connect to, instead of resolving the host in the URL.
+	///
+	/// The TLS server name and the `Host` header still come from the URL, unless they are set too.
+	pub fn set_connect_addr(
+		&mut self,
+		addr: SocketAddr,
+	) -> Option<SocketAddr> {
+		self.tcp.connect_addr.replace(addr)
+	}
+
+	/// Restricts connections to addresses of one family.
+	/// By default, both IPv6 and IPv4 addresses are tried. This has no effect on an address set with
+	/// [`set_connect_addr`](Self::set_connect_addr).
+	pub fn set_address_family(
+		&mut self,
+		family: AddressFamily,
+	) -> Option<AddressFamily> {
+		self.tcp.address_family.replace(family)
+	}
+
+	/// Sets how long the async connect methods wait for a connection attempt before trying the next address in
+	/// parallel.
+	///
+	/// When the host resolves to several addresses, they are tried in turn, alternating between IPv6 and IPv4 as
+	/// described in RFC 8305 ("Happy Eyeballs"), and the first connection to succeed is used. The default is 250
+	/// milliseconds. The blocking connect methods try the addresses one after the other instead.
+	pub fn set_connect_attempt_delay(
+		&mut self,
+		delay: Duration,
+	) -> Option<Duration> {
+		self.tcp.connect_attempt_delay.replace(delay)
+	}
+
+	/// Sets the resolver that the async connect methods use to look up the host in the URL.
+	/// By default, [`TokioResolver`](crate::TokioResolver) is used. The blocking connect methods always use the
+	/// system's resolver directly.
+	pub fn set_resolver(
+		&mut self,
+		resolver: Arc<dyn Resolver>,
+	) -> Option<Arc<dyn Resolver>> {
+		self.tcp.resolver.replace(resolver)
+	}
+
+	/// Sets how long the async connect methods wait for the resolver to look up the host.
+	/// On timeout they fail with [`Error::Timeout`]`(`[`ConnectPhase::Dns`]`)`. By default, there is no timeout.
+	pub fn set_dns_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> Option<Duration> {
+		self.tcp.dns_timeout.replace(timeout)
+	}
+
+	/// Sets how long the async connect methods wait for a TCP connection, across all the addresses that are tried.
+	/// On timeout they fail with [`Error::Timeout`]`(`[`ConnectPhase::Connect`]`)`. By default, there is no timeout.
+	pub fn set_connect_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> Option<Duration> {
+		self.tcp.connect_timeout.replace(timeout)
+	}
+
+	/// Sets how long the async connect methods wait for the TLS handshake to complete.
+	/// On timeout they fail with [`Error::Timeout`]`(`[`ConnectPhase::Tls`]`)`. By default, there is no timeout.
+	pub fn set_tls_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> Option<Duration> {
+		self.tls_timeout.replace(timeout)
+	}
+
+	/// Sets how long the async connect methods wait for the server to answer the HTTP upgrade request.
+	/// On timeout they fail with [`Error::Timeout`]`(`[`ConnectPhase::Upgrade`]`)`. By default, there is no timeout.
+	pub fn set_upgrade_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> Option<Duration> {
+		self.upgrade_timeout.replace(timeout)
+	}
+
+	/// Sets whether `TCP_NODELAY` is set on the socket, which sends small messages straight away instead of waiting
+	/// to coalesce them with later data (Nagle's algorithm).
+	/// By default, the operating system's setting is used, which normally leaves Nagle's algorithm enabled.
+	pub fn set_nodelay(
+		&mut self,
+		value: bool,
+	) -> Option<bool> {
+		self.tcp.socket_options.nodelay.replace(value)
+	}
+
+	/// Enables TCP keepalive and sets how long the connection must be idle before the first keepalive probe is sent.
+	/// By default, TCP keepalive is left as the operating system sets it, which is normally disabled.
+	pub fn set_tcp_keepalive_time(
+		&mut self,
+		time: Duration,
+	) -> Option<Duration> {
+		self.tcp
+			.socket_options
+			.keepalive_time
+			.replace(time)
+	}
+
+	/// Enables TCP keepalive and sets the time between keepalive probes that get no answer.
+	/// This is ignored on platforms that don't support setting the interval.
+	pub fn set_tcp_keepalive_interval(
+		&mut self,
+		interval: Duration,
+	) -> Option<Duration> {
+		self.tcp
+			.socket_options
+			.keepalive_interval
+			.replace(interval)
+	}
+
+	/// Sets the size of the socket's send buffer (`SO_SNDBUF`), in bytes.
+	/// The operating system may adjust the size it actually uses.
+	pub fn set_send_buffer_size(
+		&mut self,
+		size: usize,
+	) -> Option<usize> {
+		self.tcp
+			.socket_options
+			.send_buffer_size
+			.replace(size)
+	}
+
+	/// Sets the size of the socket's receive buffer (`SO_RCVBUF`), in bytes.
+	/// The operating system may adjust the size it actually uses.
+	pub fn set_recv_buffer_size(
+		&mut self,
+		size: usize,
+	) -> Option<usize> {
+		self.tcp
+			.socket_options
+			.recv_buffer_size
+			.replace(size)
+	}
+
+	/// Sets the local address that the socket is bound to before connecting, which selects the network interface
+	/// that the connection goes out through.
+	/// Only server addresses of the same family as `addr` are tried. By default, the operating system picks the
+	/// local address.
+	pub fn set_local_addr(
+		&mut self,
+		addr: IpAddr,
+	) -> Option<IpAddr> {
+		self.tcp.socket_options.local_addr.replace(addr)
+	}
+
+	/// Sets the mark (`SO_MARK`) on the socket, for use in routing and firewall rules.
+	/// Setting a mark requires the `CAP_NET_ADMIN` capability.
+	#[cfg(any(target_os = "android", target_os = "linux"))]
+	pub fn set_mark(
+		&mut self,
+		mark: u32,
+	) -> Option<u32> {
+		self.tcp.socket_options.mark.replace(mark)
+	}
+
+	/// Binds the socket to a network interface such as `eth1` (`SO_BINDTODEVICE`), so that the connection only goes
+	/// out through that interface.
+	/// Depending on the kernel version, this may require the `CAP_NET_RAW` capability.
+	#[cfg(any(target_os = "android", target_os = "linux"))]
+	pub fn set_bind_device(
+		&mut self,
+		device: String,
+	) -> Option<String> {
+		self.tcp
+			.socket_options
+			.bind_device
+			.replace(device)
+	}
+
+	/// Sets the name that the server's TLS certificate is checked against and that is sent to the server via SNI.
+	/// By default, the host in the URL is used.
+	pub fn set_server_name(
+		&mut self,
+		server_name: String,
+	) -> Option<String> {
+		self.server_name.replace(server_name)
+	}
+
+	/// Sets the value of the `Host` header in the HTTP upgrade request.
+	/// By default, the host and port in the URL are used.
+	pub fn set_host(
+		&mut self,
+		host: String,
+	) -> Option<String> {
+		self.host.replace(host)
+	}
+
+	/// Offers the `permessage-deflate` extension to the server.
+	/// If the server accepts it, messages are compressed and decompressed according to the negotiated parameters.
+	pub fn set_deflate(
+		&mut self,
+		config: DeflateConfig,
+	) -> Option<DeflateConfig> {
+		self.deflate.replace(config)
+	}
+
+	/// Offers an extension to the server, in addition to `permessage-deflate` if that has been set.
+	/// If the server accepts it, the extension is applied to every frame sent and received.
+	pub fn add_extension(
+		&mut self,
+		extension: Box<dyn WebSocketExtension>,
+	) {
+		self.extensions.push(extension);
+	}
+
+	/// Offers a subprotocol to the server.
+	///
+	/// Subprotocols are offered in the order they are added. The connection fails if the server selects a
+	/// subprotocol that was not offered. The selected subprotocol is available from
+	/// [`MessageCodec::subprotocol`] through [`AsyncClient::codec`] once connected.
+	pub fn add_subprotocol(
+		&mut self,
+		subprotocol: String,
+	) {
+		self.subprotocols.push(subprotocol);
+	}
+
+	/// Sets whether the client answers pings from the server automatically.
+	/// See [`AsyncClient::set_auto_pong`].
+	pub fn set_auto_pong(
+		&mut self,
+		value: bool,
+	) {
+		self.auto_pong = value;
+	}
+
+	/// Sends a ping to the server whenever the connection has been idle for `interval`.
+	/// By default, the client doesn't send pings. See [`AsyncClient::set_keepalive`].
+	pub fn set_ping_interval(
+		&mut self,
+		interval: Duration,
+	) -> Option<Duration> {
+		self.ping_interval.replace(interval)
+	}
+
+	/// Sets how long the client waits for a response to a ping before failing with [`Error::HeartbeatTimeout`].
+	/// By default, this is the same as the ping interval. It has no effect unless a ping interval has been set.
+	pub fn set_pong_timeout(
+		&mut self,
+		timeout: Duration,
+	) -> Option<Duration> {
+		self.pong_timeout.replace(timeout)
+	}
+
+	/// Limits the payload length of frames received from the server.
+	/// By default, there is no limit. See [`MessageCodec::with_max_frame_size`].
+	pub fn set_max_frame_size(
+		&mut self,
+		size: usize,
+	) -> Option<usize> {
+		self.max_frame_size.replace(size)
+	}
+
+	/// Limits the length of messages received from the server, after fragments have been reassembled and decompressed.
+	/// By default, there is no limit. See [`MessageCodec::with_max_message_size`].
+	pub fn set_max_message_size(
+		&mut self,
+		size: usize,
+	) -> Option<usize> {
+		self.max_message_size.replace(size)
+	}
+
+	/// Splits text and binary messages sent to the server into frames of at most `size` bytes of payload.
+	/// By default, every message is sent as a single frame. See [`MessageCodec::with_max_outgoing_frame_size`].
+	///
+	/// # Panics
+	///
+	/// This method panics if `size` is zero.
+	pub fn set_max_outgoing_frame_size(
+		&mut self,
+		size: usize,
+	) -> Option<usize> {
+		assert!(
+			size > 0,
+			"frame size must be greater than zero"
+		);
+		self.max_outgoing_frame_size.replace(size)
 	}
 
 	/// Adds an extra HTTP header for the client
+	///
+	/// The subprotocols in a `Sec-WebSocket-Protocol` header are offered after those added with
+	/// [`add_subprotocol`](Self::add_subprotocol), in a single header.
 	pub fn add_header(
 		&mut self,
 		name: String,
@@ -175,17 +519,36 @@ impl ClientBuilder {
 	/// `wss://...` URLs are not supported by this method. Use `async_connect` if you need to be able to handle
 	/// both `ws://...` and `wss://...` URLs.
 	/// This method returns an `Err` result if connecting to the server fails.
-	pub async fn async_connect_insecure(self) -> Result<AsyncClient<TokioTcpStream>> {
-		let addr = resolve(&self.url)?;
-		let stream = TokioTcpStream::connect(&addr).await?;
-		self.async_connect_on(stream).await
+	/// On success it returns the client along with the server's HTTP response and how long each phase of connecting
+	/// took.
+	pub async fn async_connect_insecure(
+		self
+	) -> Result<(
+		AsyncClient<TokioTcpStream>,
+		UpgradeResponse,
+		ConnectionInfo,
+	)> {
+		let (stream, mut info) = self.tcp.async_connect(&self.url).await?;
+
+		let started = Instant::now();
+		let (client, response) = self.async_connect_on(stream).await?;
+		info.upgrade = started.elapsed();
+
+		Ok((client, response, info))
 	}
 
 	/// Establishes a connection to the WebSocket server.
 	/// This method returns an `Err` result if connecting to the server fails.
-	pub async fn async_connect(mut self) -> Result<AsyncClient<AsyncMaybeTlsStream>> {
-		let addr = resolve(&self.url)?;
-		let stream = TokioTcpStream::connect(&addr).await?;
+	/// On success it returns the client along with the server's HTTP response and how long each phase of connecting
+	/// took.
+	pub async fn async_connect(
+		mut self
+	) -> Result<(
+		AsyncClient<AsyncMaybeTlsStream>,
+		UpgradeResponse,
+		ConnectionInfo,
+	)> {
+		let (stream, mut info) = self.tcp.async_connect(&self.url).await?;
 
 		let connector = if let Some(connector) = self.async_connector.take() {
 			connector
@@ -195,10 +558,28 @@ impl ClientBuilder {
 			AsyncConnector::Plain
 		};
 
-		let domain = self.url.domain().unwrap_or("");
-		let stream = connector.wrap(domain, stream).await?;
+		let is_tls = !matches!(
+			connector,
+			AsyncConnector::Plain
+		);
+		let server_name = self.server_name();
+		let started = Instant::now();
+		let stream = with_timeout(
+			self.tls_timeout,
+			ConnectPhase::Tls,
+			connector.wrap(&server_name, stream),
+		)
+		.await?;
+
+		if is_tls {
+			info.tls = Some(started.elapsed());
+		}
+
+		let started = Instant::now();
+		let (client, response) = self.async_connect_on(stream).await?;
+		info.upgrade = started.elapsed();
 
-		self.async_connect_on(stream).await
+		Ok((client, response, info))
 	}
 
 	/// Takes over an already established stream and uses it to send and receive WebSocket messages.
@@ -206,25 +587,446 @@ impl ClientBuilder {
 	/// This method assumes that the TLS connection has already been established, if needed. It sends an HTTP
 	/// `Connection: Upgrade` request and waits for an HTTP OK response before proceeding.
 	/// This method returns an `Err` result if writing or reading from the stream fails.
+	/// On success it returns the client along with the server's HTTP response.
 	pub async fn async_connect_on<S: AsyncRead + AsyncWrite + Unpin>(
-		self,
+		mut self,
 		mut stream: S,
-	) -> Result<AsyncClient<S>> {
+	) -> Result<(
+		AsyncClient<S>,
+		UpgradeResponse,
+	)> {
 		let mut key_base64 = [0; 24];
 		let key = make_key(self.key, &mut key_base64);
-		let upgrade_codec = UpgradeCodec::new(key);
-		let request = build_request(&self.url, key, &self.headers);
-		AsyncWriteExt::write_all(
-			&mut stream,
-			request.as_bytes(),
+		let (request, upgrade_codec) = self.start_handshake(key);
+		let (response, mut framed) = with_timeout(
+			self.upgrade_timeout,
+			ConnectPhase::Upgrade,
+			async move {
+				AsyncWriteExt::write_all(
+					&mut stream,
+					request.as_bytes(),
+				)
+				.await?;
+
+				let (opt, framed) = upgrade_codec.framed(stream).into_future().await;
+				let response = opt.ok_or_else(|| {
+					io::Error::new(
+						io::ErrorKind::UnexpectedEof,
+						"no HTTP Upgrade response",
+					)
+				})??;
+
+				Ok((response, framed))
+			},
 		)
 		.await?;
 
-		let (opt, framed) = upgrade_codec.framed(stream).into_future().await;
-		opt.ok_or_else(|| "no HTTP Upgrade response".to_owned())??;
-		Ok(replace_codec(
-			framed,
-			MessageCodec::client(),
+		let codec = self.message_codec(framed.codec_mut());
+		let mut client = AsyncClient::new(replace_codec(framed, codec));
+		client.set_auto_pong(self.auto_pong);
+		if let Some(interval) = self.ping_interval {
+			client.set_keepalive(
+				interval,
+				self.pong_timeout.unwrap_or(interval),
+			);
+		}
+
+		Ok((client, response))
+	}
+
+	/// Establishes a blocking connection to the WebSocket server.
+	///
+	/// `wss://...` URLs are not supported by this method. Use `connect` if you need to be able to handle
+	/// both `ws://...` and `wss://...` URLs.
+	/// This method returns an `Err` result if connecting to the server fails.
+	/// On success it returns the client along with the server's HTTP response.
+	pub fn connect_insecure(
+		self
+	) -> Result<(
+		Client<TcpStream>,
+		UpgradeResponse,
+	)> {
+		let stream = self
+			.tcp
+			.socket_options
+			.connect_blocking(&self.tcp.connect_addrs(&self.url)?)?;
+		self.connect_on(stream)
+	}
+
+	/// Establishes a blocking connection to the WebSocket server.
+	/// This method returns an `Err` result if connecting to the server fails.
+	/// On success it returns the client along with the server's HTTP response.
+	pub fn connect(
+		mut self
+	) -> Result<(
+		Client<MaybeTlsStream>,
+		UpgradeResponse,
+	)> {
+		let stream = self
+			.tcp
+			.socket_options
+			.connect_blocking(&self.tcp.connect_addrs(&self.url)?)?;
+
+		let connector = if let Some(connector) = self.connector.take() {
+			connector
+		} else if self.url.scheme() == "wss" {
+			Connector::new_with_default_tls_config()?
+		} else {
+			Connector::Plain
+		};
+
+		let server_name = self.server_name();
+		let stream = connector.wrap(&server_name, stream)?;
+
+		self.connect_on(stream)
+	}
+
+	/// Takes over an already established blocking stream and uses it to send and receive WebSocket messages.
+	///
+	/// This method assumes that the TLS connection has already been established, if needed. It sends an HTTP
+	/// `Connection: Upgrade` request and waits for an HTTP OK response before proceeding.
+	/// This method returns an `Err` result if writing or reading from the stream fails.
+	/// On success it returns the client along with the server's HTTP response.
+	pub fn connect_on<S: Read + Write>(
+		mut self,
+		mut stream: S,
+	) -> Result<(Client<S>, UpgradeResponse)> {
+		let mut key_base64 = [0; 24];
+		let key = make_key(self.key, &mut key_base64);
+		let (request, mut upgrade_codec) = self.start_handshake(key);
+		stream.write_all(request.as_bytes())?;
+
+		let mut read_buf = BytesMut::new();
+		let response = loop {
+			if let Some(response) = upgrade_codec.decode(&mut read_buf)? {
+				break response;
+			}
+
+			if read_into(&mut stream, &mut read_buf)? == 0 {
+				break upgrade_codec
+					.decode_eof(&mut read_buf)?
+					.ok_or_else(|| {
+						io::Error::new(
+							io::ErrorKind::UnexpectedEof,
+							"no HTTP Upgrade response",
+						)
+					})?;
+			}
+		};
+
+		let codec = self.message_codec(&mut upgrade_codec);
+		Ok((
+			Client::new(stream, codec, read_buf),
+			response,
 		))
 	}
+
+	fn server_name(&self) -> String {
+		if let Some(server_name) = &self.server_name {
+			return server_name.clone();
+		}
+
+		// IPv6 addresses appear in brackets in URLs, but TLS libraries expect them bare
+		match self.url.host() {
+			Some(Host::Domain(domain)) => domain.to_owned(),
+			Some(Host::Ipv4(addr)) => addr.to_string(),
+			Some(Host::Ipv6(addr)) => addr.to_string(),
+			None => String::new(),
+		}
+	}
+
+	/// Builds the opening handshake request, along with the codec that validates the server's response.
+	fn start_handshake(
+		&mut self,
+		key: &str,
+	) -> (String, UpgradeCodec) {
+		let extensions = self
+			.deflate
+			.take()
+			.map(|config| Box::new(PerMessageDeflate::new(config)) as Box<dyn WebSocketExtension>)
+			.into_iter()
+			.chain(mem::take(
+				&mut self.extensions,
+			));
+
+		// Subprotocols offered through `add_header` are sent and accepted along with the others
+		let subprotocols: Vec<String> = self
+			.subprotocols
+			.iter()
+			.cloned()
+			.chain(
+				self.headers
+					.iter()
+					.filter(|(name, _)| is_protocol_header(name))
+					.flat_map(|(_, value)| value.split(','))
+					.map(str::trim)
+					.filter(|subprotocol| !subprotocol.is_empty())
+					.map(str::to_owned),
+			)
+			.collect();
+
+		let mut offers = Vec::new();
+		let mut upgrade_codec = UpgradeCodec::new(key);
+		for subprotocol in &subprotocols {
+			upgrade_codec = upgrade_codec.with_subprotocol(subprotocol.clone());
+		}
+
+		for extension in extensions {
+			offers.push(extension.offer());
+			upgrade_codec = upgrade_codec.with_extension(extension);
+		}
+
+		let extensions = if offers.is_empty() {
+			None
+		} else {
+			Some(offers.join(", "))
+		};
+
+		let request = build_request(
+			&self.url,
+			self.host.as_deref(),
+			key,
+			&subprotocols,
+			extensions.as_deref(),
+			&self.headers,
+		);
+
+		(request, upgrade_codec)
+	}
+
+	/// Builds the codec for the connection once the server has accepted the opening handshake.
+	fn message_codec(
+		&self,
+		upgrade_codec: &mut UpgradeCodec,
+	) -> MessageCodec {
+		let mut codec = MessageCodec::client();
+		for extension in upgrade_codec.take_extensions() {
+			codec = codec.with_extension(extension);
+		}
+
+		if let Some(subprotocol) = upgrade_codec.take_subprotocol() {
+			codec = codec.with_subprotocol(subprotocol);
+		}
+
+		if let Some(size) = self.max_frame_size {
+			codec = codec.with_max_frame_size(size);
+		}
+
+		if let Some(size) = self.max_message_size {
+			codec = codec.with_max_message_size(size);
+		}
+
+		if let Some(size) = self.max_outgoing_frame_size {
+			codec = codec.with_max_outgoing_frame_size(size);
+		}
+
+		codec
+	}
+}
+
+/// The settings for opening the TCP connection.
+///
+/// These are kept apart from the rest of the builder, which holds extensions that aren't `Sync`, so that the async
+/// connect methods can borrow them across an `await` and still return a `Send` future.
+#[derive(Default)]
+struct TcpSettings {
+	connect_addr: Option<SocketAddr>,
+	address_family: Option<AddressFamily>,
+	connect_attempt_delay: Option<Duration>,
+	resolver: Option<Arc<dyn Resolver>>,
+	dns_timeout: Option<Duration>,
+	connect_timeout: Option<Duration>,
+	socket_options: SocketOptions,
+}
+
+impl TcpSettings {
+	/// Returns the addresses to try, in order.
+	fn connect_addrs(
+		&self,
+		url: &Url,
+	) -> Result<Vec<SocketAddr>> {
+		if let Some(addr) = self.connect_addr {
+			return Ok(vec![addr]);
+		}
+
+		self.order_addrs(url, resolve(url)?)
+	}
+
+	/// Returns the addresses to try, in order, looking up the host with the resolver if needed.
+	/// Also returns how long the lookup took, if there was one.
+	async fn async_connect_addrs(
+		&self,
+		url: &Url,
+	) -> Result<(
+		Vec<SocketAddr>,
+		Option<Duration>,
+	)> {
+		if let Some(addr) = self.connect_addr {
+			return Ok((vec![addr], None));
+		}
+
+		let port = url.port_or_known_default().ok_or_else(|| {
+			Error::Url(format!(
+				"URL has no port: {url}",
+				url = url
+			))
+		})?;
+
+		let mut dns = None;
+		let addrs = match url.host() {
+			Some(Host::Domain(domain)) => {
+				let lookup = match &self.resolver {
+					Some(resolver) => resolver.resolve(domain, port),
+					None => TokioResolver.resolve(domain, port),
+				};
+
+				let started = Instant::now();
+				let addrs = with_timeout(
+					self.dns_timeout,
+					ConnectPhase::Dns,
+					async { Ok(lookup.await?) },
+				)
+				.await?;
+
+				dns = Some(started.elapsed());
+
+				if addrs.is_empty() {
+					return Err(Error::Url(format!(
+						"can't resolve host: {url}",
+						url = url
+					)));
+				}
+
+				addrs
+			}
+			Some(Host::Ipv4(addr)) => vec![SocketAddr::from((addr, port))],
+			Some(Host::Ipv6(addr)) => vec![SocketAddr::from((addr, port))],
+			None => {
+				return Err(Error::Url(format!(
+					"URL has no host: {url}",
+					url = url
+				)))
+			}
+		};
+
+		Ok((
+			self.order_addrs(url, addrs)?,
+			dns,
+		))
+	}
+
+	/// Applies the address family restriction and interleaves the address families.
+	fn order_addrs(
+		&self,
+		url: &Url,
+		mut addrs: Vec<SocketAddr>,
+	) -> Result<Vec<SocketAddr>> {
+		// A socket bound to a local address can only reach addresses of the same family
+		let family = self.address_family.or_else(|| {
+			self.socket_options.local_addr.map(|ip| match ip {
+				IpAddr::V4(_) => AddressFamily::Ipv4,
+				IpAddr::V6(_) => AddressFamily::Ipv6,
+			})
+		});
+
+		if let Some(family) = family {
+			addrs.retain(|addr| family.contains(addr));
+			if addrs.is_empty() {
+				return Err(Error::Url(format!(
+					"host has no {family} address: {url}",
+					url = url
+				)));
+			}
+		}
+
+		Ok(interleave(addrs))
+	}
+
+	async fn async_connect(
+		&self,
+		url: &Url,
+	) -> Result<(TokioTcpStream, ConnectionInfo)> {
+		let (addrs, dns) = self.async_connect_addrs(url).await?;
+		let attempt_delay = self
+			.connect_attempt_delay
+			.unwrap_or(DEFAULT_CONNECT_ATTEMPT_DELAY);
+
+		let started = Instant::now();
+		let stream = with_timeout(
+			self.connect_timeout,
+			ConnectPhase::Connect,
+			async {
+				Ok(connect_racing(
+					addrs,
+					attempt_delay,
+					&self.socket_options,
+				)
+				.await?)
+			},
+		)
+		.await?;
+
+		let info = ConnectionInfo {
+			peer_addr: stream.peer_addr()?,
+			dns,
+			connect: started.elapsed(),
+			tls: None,
+			upgrade: Duration::ZERO,
+		};
+
+		Ok((stream, info))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Message, ReconnectingClient};
+
+	fn assert_send<T: Send>(_: T) {}
+
+	#[test]
+	fn connect_futures_are_send() {
+		let builder = || ClientBuilder::new("ws://localhost").unwrap();
+		assert_send(builder().async_connect());
+		assert_send(builder().async_connect_insecure());
+
+		let mut client = ReconnectingClient::new(builder);
+		assert_send(client.next());
+		assert_send(client.send(Message::text("hello")));
+	}
+
+	#[test]
+	fn offer_subprotocols_from_header() {
+		let mut builder = ClientBuilder::new("ws://localhost").unwrap();
+		builder.add_subprotocol("a".to_owned());
+		builder.add_header(
+			"sec-websocket-protocol".to_owned(),
+			"b, c".to_owned(),
+		);
+
+		let (request, mut upgrade_codec) = builder.start_handshake("dGhlIHNhbXBsZSBub25jZQ==");
+		let request = request.to_ascii_lowercase();
+		assert_eq!(
+			request.matches("sec-websocket-protocol").count(),
+			1
+		);
+		assert!(request.contains("sec-websocket-protocol: a, b, c\r\n"));
+
+		let mut response = BytesMut::from(
+			"HTTP/1.1 101 Switching Protocols\r\n\
+			 Upgrade: websocket\r\n\
+			 Connection: Upgrade\r\n\
+			 Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+			 Sec-WebSocket-Protocol: c\r\n\r\n",
+		);
+		upgrade_codec
+			.decode(&mut response)
+			.unwrap()
+			.unwrap();
+		assert_eq!(
+			upgrade_codec.take_subprotocol().as_deref(),
+			Some("c")
+		);
+	}
 }
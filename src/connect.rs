@@ -0,0 +1,411 @@
+use std::future::{poll_fn, Future};
+use std::net::{IpAddr, SocketAddr};
+use std::task::Poll;
+use std::time::Duration;
+use std::{fmt, io, net};
+
+use futures_util::stream::FuturesUnordered;
+use futures_util::StreamExt;
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+use tokio::net::{TcpSocket, TcpStream};
+
+use crate::{ConnectPhase, Error, Result};
+
+/// Delay between starting connection attempts to successive addresses, as recommended by RFC 8305.
+pub(crate) const DEFAULT_CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// An IP address family that connections can be restricted to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressFamily {
+	/// Only connect to IPv4 addresses.
+	Ipv4,
+	/// Only connect to IPv6 addresses.
+	Ipv6,
+}
+
+/// Describes how a connection was established by [`ClientBuilder::async_connect`](crate::ClientBuilder::async_connect)
+/// or [`ClientBuilder::async_connect_insecure`](crate::ClientBuilder::async_connect_insecure), including how long each
+/// phase took.
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+	pub(crate) peer_addr: SocketAddr,
+	pub(crate) dns: Option<Duration>,
+	pub(crate) connect: Duration,
+	pub(crate) tls: Option<Duration>,
+	pub(crate) upgrade: Duration,
+}
+
+impl ConnectionInfo {
+	/// Returns the address that the TCP connection was made to.
+	#[must_use]
+	pub fn peer_addr(&self) -> SocketAddr {
+		self.peer_addr
+	}
+
+	/// Returns how long it took to look up the host, or `None` if the URL holds an IP address or the connect
+	/// address was set on the builder.
+	#[must_use]
+	pub fn dns_duration(&self) -> Option<Duration> {
+		self.dns
+	}
+
+	/// Returns how long it took to open the TCP connection, including attempts to any other addresses that were
+	/// raced against it.
+	#[must_use]
+	pub fn connect_duration(&self) -> Duration {
+		self.connect
+	}
+
+	/// Returns how long the TLS handshake took, or `None` if the connection is not protected with TLS.
+	#[must_use]
+	pub fn tls_duration(&self) -> Option<Duration> {
+		self.tls
+	}
+
+	/// Returns the time from sending the HTTP upgrade request to receiving the server's response.
+	#[must_use]
+	pub fn upgrade_duration(&self) -> Duration {
+		self.upgrade
+	}
+
+	/// Returns the sum of the durations of all the phases.
+	#[must_use]
+	pub fn total_duration(&self) -> Duration {
+		self.dns.unwrap_or_default() + self.connect + self.tls.unwrap_or_default() + self.upgrade
+	}
+}
+
+/// Options applied to every socket before it connects.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SocketOptions {
+	pub(crate) nodelay: Option<bool>,
+	pub(crate) keepalive_time: Option<Duration>,
+	pub(crate) keepalive_interval: Option<Duration>,
+	pub(crate) send_buffer_size: Option<usize>,
+	pub(crate) recv_buffer_size: Option<usize>,
+	pub(crate) local_addr: Option<IpAddr>,
+	#[cfg(any(target_os = "android", target_os = "linux"))]
+	pub(crate) mark: Option<u32>,
+	#[cfg(any(target_os = "android", target_os = "linux"))]
+	pub(crate) bind_device: Option<String>,
+}
+
+impl SocketOptions {
+	fn socket(
+		&self,
+		addr: SocketAddr,
+	) -> io::Result<Socket> {
+		let socket = Socket::new(
+			Domain::for_address(addr),
+			Type::STREAM,
+			Some(Protocol::TCP),
+		)?;
+
+		if let Some(nodelay) = self.nodelay {
+			socket.set_nodelay(nodelay)?;
+		}
+
+		if self.keepalive_time.is_some() || self.keepalive_interval.is_some() {
+			let mut keepalive = TcpKeepalive::new();
+			if let Some(time) = self.keepalive_time {
+				keepalive = keepalive.with_time(time);
+			}
+
+			#[cfg(any(
+				target_os = "android",
+				target_os = "dragonfly",
+				target_os = "freebsd",
+				target_os = "fuchsia",
+				target_os = "illumos",
+				target_os = "ios",
+				target_os = "linux",
+				target_os = "macos",
+				target_os = "netbsd",
+				target_os = "tvos",
+				target_os = "watchos",
+				target_os = "windows",
+			))]
+			if let Some(interval) = self.keepalive_interval {
+				keepalive = keepalive.with_interval(interval);
+			}
+
+			socket.set_tcp_keepalive(&keepalive)?;
+		}
+
+		if let Some(size) = self.send_buffer_size {
+			socket.set_send_buffer_size(size)?;
+		}
+
+		if let Some(size) = self.recv_buffer_size {
+			socket.set_recv_buffer_size(size)?;
+		}
+
+		#[cfg(any(target_os = "android", target_os = "linux"))]
+		if let Some(mark) = self.mark {
+			socket.set_mark(mark)?;
+		}
+
+		#[cfg(any(target_os = "android", target_os = "linux"))]
+		if let Some(device) = &self.bind_device {
+			socket.bind_device(Some(device.as_bytes()))?;
+		}
+
+		if let Some(ip) = self.local_addr {
+			socket.bind(&SocketAddr::new(ip, 0).into())?;
+		}
+
+		Ok(socket)
+	}
+
+	async fn connect(
+		&self,
+		addr: SocketAddr,
+	) -> io::Result<TcpStream> {
+		let socket = self.socket(addr)?;
+		socket.set_nonblocking(true)?;
+		TcpSocket::from_std_stream(socket.into())
+			.connect(addr)
+			.await
+	}
+
+	/// Connects to each address in turn until one accepts the connection, blocking the current thread.
+	pub(crate) fn connect_blocking(
+		&self,
+		addrs: &[SocketAddr],
+	) -> io::Result<net::TcpStream> {
+		let mut last_error = None;
+		for &addr in addrs {
+			let result = self.socket(addr).and_then(|socket| {
+				socket.connect(&addr.into())?;
+				Ok(socket.into())
+			});
+
+			match result {
+				Ok(stream) => return Ok(stream),
+				Err(err) => last_error = Some(err),
+			}
+		}
+
+		Err(last_error.unwrap_or_else(no_addresses))
+	}
+}
+
+fn no_addresses() -> io::Error {
+	io::Error::new(
+		io::ErrorKind::InvalidInput,
+		"no addresses to connect to",
+	)
+}
+
+impl fmt::Display for AddressFamily {
+	fn fmt(
+		&self,
+		f: &mut fmt::Formatter<'_>,
+	) -> fmt::Result {
+		match self {
+			Self::Ipv4 => f.write_str("IPv4"),
+			Self::Ipv6 => f.write_str("IPv6"),
+		}
+	}
+}
+
+impl AddressFamily {
+	pub(crate) fn contains(
+		self,
+		addr: &SocketAddr,
+	) -> bool {
+		match self {
+			Self::Ipv4 => addr.is_ipv4(),
+			Self::Ipv6 => addr.is_ipv6(),
+		}
+	}
+}
+
+/// Interleaves IPv6 and IPv4 addresses, starting with the family of the first address, as described in RFC 8305
+/// section 4.
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+	let Some(first) = addrs.first() else {
+		return addrs;
+	};
+
+	let first_is_ipv6 = first.is_ipv6();
+	let len = addrs.len();
+	let (preferred, other): (Vec<_>, Vec<_>) = addrs
+		.into_iter()
+		.partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+	let mut preferred = preferred.into_iter();
+	let mut other = other.into_iter();
+	let mut interleaved = Vec::with_capacity(len);
+	loop {
+		match (preferred.next(), other.next()) {
+			(None, None) => return interleaved,
+			(a, b) => interleaved.extend(a.into_iter().chain(b)),
+		}
+	}
+}
+
+/// Connects to the first address that accepts the connection.
+///
+/// Attempts are started in order, each one `attempt_delay` after the previous one or as soon as the previous one
+/// fails, and run concurrently; the first to succeed wins and the rest are dropped. If every attempt fails, the error
+/// from the last one to fail is returned.
+pub(crate) async fn connect_racing(
+	addrs: Vec<SocketAddr>,
+	attempt_delay: Duration,
+	options: &SocketOptions,
+) -> io::Result<TcpStream> {
+	let mut pending = addrs.into_iter();
+	let mut attempts = FuturesUnordered::new();
+	let mut last_error = None;
+	loop {
+		match pending.next() {
+			Some(addr) => attempts.push(options.connect(addr)),
+			None if attempts.is_empty() => return Err(last_error.unwrap_or_else(no_addresses)),
+			None => {}
+		}
+
+		let mut timer = Box::pin(tokio::time::sleep(
+			attempt_delay,
+		));
+		let finished = poll_fn(|cx| {
+			if let Poll::Ready(Some(result)) = attempts.poll_next_unpin(cx) {
+				return Poll::Ready(Some(result));
+			}
+
+			// Don't bother with the timer once there's nothing left to start
+			if pending.len() > 0 && timer.as_mut().poll(cx).is_ready() {
+				return Poll::Ready(None);
+			}
+
+			Poll::Pending
+		})
+		.await;
+
+		match finished {
+			Some(Ok(stream)) => return Ok(stream),
+			Some(Err(err)) => last_error = Some(err),
+			None => {}
+		}
+	}
+}
+
+/// Runs `future`, failing with [`Error::Timeout`] if it takes longer than `timeout`.
+pub(crate) async fn with_timeout<T>(
+	timeout: Option<Duration>,
+	phase: ConnectPhase,
+	future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+	match timeout {
+		Some(timeout) => tokio::time::timeout(timeout, future)
+			.await
+			.map_err(|_| Error::Timeout(phase))?,
+		None => future.await,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::{Ipv4Addr, Ipv6Addr};
+
+	use tokio::net::TcpListener;
+
+	use super::*;
+
+	fn v4(port: u16) -> SocketAddr {
+		SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+	}
+
+	fn v6(port: u16) -> SocketAddr {
+		SocketAddr::from((Ipv6Addr::LOCALHOST, port))
+	}
+
+	/// Returns an address that refuses connections.
+	fn refused_addr() -> SocketAddr {
+		let listener = net::TcpListener::bind(v4(0)).unwrap();
+		listener.local_addr().unwrap()
+	}
+
+	#[test]
+	fn interleave_families() {
+		assert_eq!(
+			interleave(vec![
+				v6(1),
+				v6(2),
+				v6(3),
+				v4(4),
+				v4(5)
+			]),
+			[v6(1), v4(4), v6(2), v4(5), v6(3)]
+		);
+		assert_eq!(
+			interleave(vec![v4(1), v4(2), v6(3)]),
+			[v4(1), v6(3), v4(2)]
+		);
+		assert_eq!(
+			interleave(vec![
+				v4(1),
+				v6(2),
+				v4(3),
+				v6(4)
+			]),
+			[v4(1), v6(2), v4(3), v6(4)]
+		);
+		assert_eq!(
+			interleave(vec![v4(2), v4(1)]),
+			[v4(2), v4(1)]
+		);
+		assert_eq!(interleave(Vec::new()), []);
+	}
+
+	#[tokio::test]
+	async fn connect_racing_skips_refused_address() {
+		let listener = TcpListener::bind(v4(0)).await.unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		// The refused attempt must hand over to the next address straight away, without waiting for the delay
+		let stream = tokio::time::timeout(
+			Duration::from_secs(5),
+			connect_racing(
+				vec![refused_addr(), addr],
+				Duration::from_secs(60),
+				&SocketOptions::default(),
+			),
+		)
+		.await
+		.unwrap()
+		.unwrap();
+
+		assert_eq!(
+			stream.peer_addr().unwrap(),
+			addr
+		);
+	}
+
+	#[tokio::test]
+	async fn connect_racing_fails_when_every_address_does() {
+		let err = connect_racing(
+			vec![refused_addr(), refused_addr()],
+			Duration::from_secs(60),
+			&SocketOptions::default(),
+		)
+		.await
+		.unwrap_err();
+		assert_eq!(
+			err.kind(),
+			io::ErrorKind::ConnectionRefused
+		);
+
+		let err = connect_racing(
+			Vec::new(),
+			Duration::from_secs(60),
+			&SocketOptions::default(),
+		)
+		.await
+		.unwrap_err();
+		assert_eq!(
+			err.kind(),
+			io::ErrorKind::InvalidInput
+		);
+	}
+}
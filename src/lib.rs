@@ -1,13 +1,31 @@
 //! A fast, low-overhead WebSocket client.
 
+macro_rules! writeok {
+    ($dst:expr, $($arg:tt)*) => {
+        let _ = std::fmt::Write::write_fmt(&mut $dst, format_args!($($arg)*));
+    }
+}
+
+mod async_client;
 mod client;
+mod connect;
+mod reconnect;
+mod resolver;
+mod server;
 mod ssl;
+mod sync_client;
+mod tls;
 
+pub use crate::async_client::{AsyncClient, CloseState};
 pub use crate::client::ClientBuilder;
-pub use crate::ssl::{AsyncConnector, AsyncMaybeTlsStream, Connector};
-pub use websocket_codec::{CloseCode, CloseFrame, Error, Message, MessageCodec, Opcode, Result};
-
-use tokio_util::codec::Framed;
-
-/// Exposes a `Sink` and a `Stream` for sending and receiving WebSocket messages asynchronously.
-pub type AsyncClient<S> = Framed<S, MessageCodec>;
+pub use crate::connect::{AddressFamily, ConnectionInfo};
+pub use crate::reconnect::{Backoff, OnConnectedFuture, ReconnectingClient};
+pub use crate::resolver::{ResolveFuture, Resolver, TokioResolver};
+pub use crate::server::{accept, ServerBuilder};
+pub use crate::ssl::{AsyncConnector, AsyncMaybeTlsStream, Connector, MaybeTlsStream};
+pub use crate::sync_client::Client;
+pub use crate::tls::TlsBuilder;
+pub use websocket_codec::{
+	protocol, CloseCode, CloseFrame, ConnectPhase, DeflateConfig, Error, Fragment, FrameHeader, HandshakeError,
+	Message, MessageCodec, Opcode, ProtocolError, Result, UpgradeResponse, WebSocketExtension,
+};
@@ -0,0 +1,498 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use rand::Rng;
+
+use crate::{AsyncClient, AsyncMaybeTlsStream, ClientBuilder, CloseCode, CloseState, Error, Message, Opcode, Result};
+
+/// The future returned by the callback passed to [`ReconnectingClient::set_on_connected`].
+pub type OnConnectedFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+type OnConnected = Box<dyn for<'a> FnMut(&'a mut AsyncClient<AsyncMaybeTlsStream>) -> OnConnectedFuture<'a> + Send>;
+
+/// Delay to use when the server closes the connection with [`CloseCode::Restart`], as suggested by RFC 6455.
+const RESTART_DELAY_SECS: (u64, u64) = (5, 30);
+
+/// Controls how long a [`ReconnectingClient`] waits between connection attempts.
+///
+/// The delay starts at the initial delay and is multiplied after every failed attempt, up to the maximum delay. A
+/// random fraction of the delay, up to the jitter factor, is then subtracted so that many clients don't reconnect at
+/// the same moment.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+	initial_delay: Duration,
+	max_delay: Duration,
+	multiplier: f64,
+	jitter: f64,
+	max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Backoff {
+	/// Returns a `Backoff` that starts at 500 milliseconds, doubles after every failed attempt up to 30 seconds,
+	/// applies up to 50% jitter and never gives up.
+	#[must_use]
+	pub fn new() -> Self {
+		Self {
+			initial_delay: Duration::from_millis(500),
+			max_delay: Duration::from_secs(30),
+			multiplier: 2.0,
+			jitter: 0.5,
+			max_attempts: None,
+		}
+	}
+
+	/// Sets the delay before the first attempt to reconnect.
+	pub fn set_initial_delay(
+		&mut self,
+		delay: Duration,
+	) {
+		self.initial_delay = delay;
+	}
+
+	/// Sets the longest delay between two attempts.
+	pub fn set_max_delay(
+		&mut self,
+		delay: Duration,
+	) {
+		self.max_delay = delay;
+	}
+
+	/// Sets the factor that the delay is multiplied by after every failed attempt.
+	///
+	/// # Panics
+	///
+	/// This method panics if `multiplier` is less than 1, infinite or NaN.
+	pub fn set_multiplier(
+		&mut self,
+		multiplier: f64,
+	) {
+		assert!(
+			multiplier.is_finite() && multiplier >= 1.0,
+			"backoff multiplier must be finite and at least 1"
+		);
+		self.multiplier = multiplier;
+	}
+
+	/// Sets the largest fraction of the delay, between 0 and 1, that is randomly removed from every delay.
+	///
+	/// # Panics
+	///
+	/// This method panics if `jitter` is NaN.
+	pub fn set_jitter(
+		&mut self,
+		jitter: f64,
+	) {
+		assert!(
+			!jitter.is_nan(),
+			"backoff jitter must not be NaN"
+		);
+		self.jitter = jitter.clamp(0.0, 1.0);
+	}
+
+	/// Sets the number of consecutive failed attempts after which the client gives up.
+	/// By default, the client keeps trying forever.
+	pub fn set_max_attempts(
+		&mut self,
+		attempts: Option<u32>,
+	) {
+		self.max_attempts = attempts;
+	}
+
+	fn delay(
+		&self,
+		failures: u32,
+	) -> Duration {
+		let exponent = i32::try_from(failures).unwrap_or(i32::MAX);
+		let delay =
+			(self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent)).min(self.max_delay.as_secs_f64());
+
+		let jitter = rand::thread_rng().gen_range(0.0..=self.jitter);
+
+		// Delays too long to represent, such as a maximum of `Duration::MAX`, are as good as the maximum
+		Duration::try_from_secs_f64(delay * (1.0 - jitter)).unwrap_or(self.max_delay)
+	}
+}
+
+/// A client that reconnects to the server whenever the connection is lost.
+///
+/// A new [`ClientBuilder`] is requested from the factory function for every connection, so that headers and
+/// extensions are set up from scratch each time. After every successful connection, the callback set with
+/// [`set_on_connected`](Self::set_on_connected) runs before any other message is sent or received, which makes it
+/// the place to replay subscriptions.
+///
+/// Close frames from the server are returned from [`next`](Self::next) like any other message. A close with
+/// [`CloseCode::Restart`] delays the next connection by 5 to 30 seconds, and a close with [`CloseCode::Again`] is
+/// treated like a failed attempt, so the backoff keeps growing. Any other lost connection is retried after the
+/// current backoff delay; the backoff is reset once a message other than a close frame has been received.
+pub struct ReconnectingClient {
+	factory: Box<dyn Fn() -> ClientBuilder + Send + Sync>,
+	backoff: Backoff,
+	on_connected: Option<OnConnected>,
+	client: Option<AsyncClient<AsyncMaybeTlsStream>>,
+	failures: u32,
+	delay: Option<Duration>,
+}
+
+impl ReconnectingClient {
+	/// Creates a `ReconnectingClient` that connects with the builders returned by `factory`.
+	///
+	/// No connection is made until the first message is sent or received.
+	pub fn new<F>(factory: F) -> Self
+	where
+		F: Fn() -> ClientBuilder + Send + Sync + 'static,
+	{
+		Self {
+			factory: Box::new(factory),
+			backoff: Backoff::new(),
+			on_connected: None,
+			client: None,
+			failures: 0,
+			delay: None,
+		}
+	}
+
+	/// Sets the delays between connection attempts.
+	pub fn set_backoff(
+		&mut self,
+		backoff: Backoff,
+	) {
+		self.backoff = backoff;
+	}
+
+	/// Sets a callback that runs after every successful connection, before any other message is sent or received.
+	///
+	/// If the callback returns `Err`, the connection is dropped and counts as a failed attempt.
+	pub fn set_on_connected<F>(
+		&mut self,
+		on_connected: F,
+	) where
+		F: for<'a> FnMut(&'a mut AsyncClient<AsyncMaybeTlsStream>) -> OnConnectedFuture<'a> + Send + 'static,
+	{
+		self.on_connected = Some(Box::new(on_connected));
+	}
+
+	/// Returns the current connection, if there is one.
+	pub fn get_mut(&mut self) -> Option<&mut AsyncClient<AsyncMaybeTlsStream>> {
+		self.client.as_mut()
+	}
+
+	/// Receives the next message, reconnecting first if needed.
+	///
+	/// # Errors
+	///
+	/// This method returns an `Err` result if the connection fails, such as with [`Error::HeartbeatTimeout`] or a
+	/// protocol error, in which case the connection is dropped and the next call connects again. It also returns an
+	/// `Err` result once the backoff's maximum number of attempts has been reached, with the error from the last
+	/// attempt.
+	pub async fn next(&mut self) -> Result<Message> {
+		loop {
+			let client = self.connect().await?;
+			match client.next().await {
+				Some(Ok(message)) => {
+					if message.opcode() == Opcode::Close {
+						self.closed_by_server(&message);
+					} else {
+						self.failures = 0;
+					}
+
+					return Ok(message);
+				}
+				Some(Err(err)) => {
+					self.disconnected();
+					return Err(err);
+				}
+				None => self.disconnected(),
+			}
+		}
+	}
+
+	/// Sends a message, reconnecting first if needed.
+	///
+	/// A connection on which either side has sent a close frame counts as lost, so the message goes out on a new one.
+	///
+	/// # Errors
+	///
+	/// This method returns an `Err` result if sending fails, in which case the connection is dropped and the message
+	/// is lost, or once the backoff's maximum number of attempts has been reached.
+	pub async fn send(
+		&mut self,
+		message: Message,
+	) -> Result<()> {
+		// After a close frame the connection can't carry the message, so connect again instead of dropping it
+		if self
+			.client
+			.as_ref()
+			.is_some_and(|client| client.state() != CloseState::Open)
+		{
+			self.disconnected();
+		}
+
+		let result = self.connect().await?.send(message).await;
+		if result.is_err() {
+			self.disconnected();
+		}
+
+		result
+	}
+
+	/// Closes the current connection, if there is one.
+	///
+	/// The next call to [`next`](Self::next) or [`send`](Self::send) connects again.
+	///
+	/// # Errors
+	///
+	/// This method returns an `Err` result if the closing handshake fails.
+	pub async fn close(
+		&mut self,
+		code: CloseCode,
+		reason: &str,
+	) -> Result<()> {
+		match self.client.take() {
+			Some(mut client) => client.close(code, reason).await,
+			None => Ok(()),
+		}
+	}
+
+	fn disconnected(&mut self) {
+		self.client = None;
+		if self.delay.is_none() {
+			self.delay = Some(self.backoff.delay(self.failures));
+		}
+	}
+
+	fn closed_by_server(
+		&mut self,
+		message: &Message,
+	) {
+		match message.as_close().map(|frame| frame.code()) {
+			Some(CloseCode::Restart) => {
+				let (min, max) = RESTART_DELAY_SECS;
+				let secs = rand::thread_rng().gen_range(min..=max);
+				self.delay = Some(Duration::from_secs(secs));
+			}
+			Some(CloseCode::Again) => {
+				self.failures += 1;
+				self.delay = Some(self.backoff.delay(self.failures));
+			}
+			_ => {}
+		}
+	}
+
+	async fn connect(&mut self) -> Result<&mut AsyncClient<AsyncMaybeTlsStream>> {
+		if self.client.is_none() {
+			loop {
+				if let Some(delay) = self.delay.take() {
+					tokio::time::sleep(delay).await;
+				}
+
+				match self.try_connect().await {
+					Ok(client) => {
+						self.client = Some(client);
+						break;
+					}
+					Err(err) => {
+						self.failures += 1;
+						if self
+							.backoff
+							.max_attempts
+							.is_some_and(|max_attempts| self.failures >= max_attempts)
+						{
+							self.failures = 0;
+							return Err(err);
+						}
+
+						let retry_after = match &err {
+							Error::Handshake(err) => err.retry_after(),
+							_ => None,
+						};
+
+						self.delay = Some(retry_after.unwrap_or_else(|| self.backoff.delay(self.failures)));
+					}
+				}
+			}
+		}
+
+		Ok(self.client.as_mut().expect("client is connected"))
+	}
+
+	async fn try_connect(&mut self) -> Result<AsyncClient<AsyncMaybeTlsStream>> {
+		let (mut client, _response, _info) = (self.factory)().async_connect().await?;
+		if let Some(on_connected) = &mut self.on_connected {
+			on_connected(&mut client).await?;
+		}
+
+		Ok(client)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::{self, Ipv4Addr, SocketAddr};
+
+	use tokio::net::TcpListener;
+
+	use super::*;
+
+	fn backoff(
+		initial_delay: Duration,
+		max_delay: Duration,
+		jitter: f64,
+	) -> Backoff {
+		let mut backoff = Backoff::new();
+		backoff.set_initial_delay(initial_delay);
+		backoff.set_max_delay(max_delay);
+		backoff.set_jitter(jitter);
+		backoff
+	}
+
+	fn client(url: String) -> ReconnectingClient {
+		let mut client = ReconnectingClient::new(move || ClientBuilder::new(&url).unwrap());
+		client.set_backoff(backoff(
+			Duration::from_millis(1),
+			Duration::from_millis(1),
+			0.0,
+		));
+		client
+	}
+
+	#[test]
+	fn backoff_grows_up_to_max_delay() {
+		let backoff = backoff(
+			Duration::from_millis(100),
+			Duration::from_secs(1),
+			0.0,
+		);
+
+		assert_eq!(
+			backoff.delay(0),
+			Duration::from_millis(100)
+		);
+		assert_eq!(
+			backoff.delay(1),
+			Duration::from_millis(200)
+		);
+		assert_eq!(
+			backoff.delay(3),
+			Duration::from_millis(800)
+		);
+		assert_eq!(
+			backoff.delay(4),
+			Duration::from_secs(1)
+		);
+		assert_eq!(
+			backoff.delay(u32::MAX),
+			Duration::from_secs(1)
+		);
+	}
+
+	#[test]
+	fn backoff_jitter_shortens_delay() {
+		let backoff = backoff(
+			Duration::from_secs(1),
+			Duration::from_secs(1),
+			0.5,
+		);
+
+		for _ in 0..100 {
+			let delay = backoff.delay(0);
+			assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1));
+		}
+	}
+
+	#[tokio::test]
+	async fn give_up_after_max_attempts() {
+		let addr = net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+			.unwrap()
+			.local_addr()
+			.unwrap();
+
+		let mut client = client(format!("ws://{addr}"));
+		let mut backoff = client.backoff.clone();
+		backoff.set_max_attempts(Some(3));
+		client.set_backoff(backoff);
+
+		assert!(client.next().await.is_err());
+		assert_eq!(client.failures, 0);
+	}
+
+	#[test]
+	fn restart_close_delays_reconnect() {
+		let mut client = client("ws://localhost".to_owned());
+		client.closed_by_server(&Message::close_with_reason(
+			CloseCode::Restart,
+			String::new(),
+		));
+
+		let delay = client.delay.unwrap();
+		assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(30));
+		assert_eq!(client.failures, 0);
+	}
+
+	#[test]
+	fn again_close_counts_as_failure() {
+		let mut client = client("ws://localhost".to_owned());
+		client.set_backoff(backoff(
+			Duration::from_millis(100),
+			Duration::from_secs(10),
+			0.0,
+		));
+
+		client.closed_by_server(&Message::close_with_reason(
+			CloseCode::Again,
+			String::new(),
+		));
+		client.closed_by_server(&Message::close_with_reason(
+			CloseCode::Again,
+			String::new(),
+		));
+
+		assert_eq!(client.failures, 2);
+		assert_eq!(
+			client.delay,
+			Some(Duration::from_millis(400))
+		);
+	}
+
+	#[tokio::test]
+	async fn send_after_server_close_reconnects() {
+		let listener = TcpListener::bind(SocketAddr::from((
+			Ipv4Addr::LOCALHOST,
+			0,
+		)))
+		.await
+		.unwrap();
+
+		let addr = listener.local_addr().unwrap();
+		let server = tokio::spawn(async move {
+			let (stream, _) = listener.accept().await.unwrap();
+			let mut first = crate::accept(stream).await.unwrap();
+			first.send(Message::close()).await.unwrap();
+
+			let (stream, _) = listener.accept().await.unwrap();
+			let mut second = crate::accept(stream).await.unwrap();
+			let message = second.next().await.unwrap().unwrap();
+			(first, message)
+		});
+
+		let mut client = client(format!("ws://{addr}"));
+		assert_eq!(
+			client.next().await.unwrap().opcode(),
+			Opcode::Close
+		);
+		client.send(Message::text("hello")).await.unwrap();
+
+		let (_first, message) = server.await.unwrap();
+		assert_eq!(
+			message.as_text(),
+			Some("hello")
+		);
+	}
+}
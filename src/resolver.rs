@@ -0,0 +1,44 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+/// The future returned by [`Resolver::resolve`].
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send + 'a>>;
+
+/// Looks up the addresses of a host name for the async connect methods of [`ClientBuilder`](crate::ClientBuilder).
+///
+/// Hosts that are given as IP addresses in the URL are connected to directly, without going through the resolver.
+/// Implementations can serve static test fixtures, cache results or use their own DNS client.
+pub trait Resolver: Send + Sync {
+	/// Returns the addresses of `host`, with `port` filled in.
+	///
+	/// The addresses are tried in the order returned, alternating between IPv6 and IPv4. Returning no addresses is
+	/// treated as a failure to resolve the host.
+	fn resolve<'a>(
+		&'a self,
+		host: &'a str,
+		port: u16,
+	) -> ResolveFuture<'a>;
+}
+
+/// The default resolver, which runs the system's resolver on Tokio's blocking thread pool via
+/// [`tokio::net::lookup_host`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioResolver;
+
+impl Resolver for TokioResolver {
+	fn resolve<'a>(
+		&'a self,
+		host: &'a str,
+		port: u16,
+	) -> ResolveFuture<'a> {
+		Box::pin(async move {
+			Ok(
+				tokio::net::lookup_host((host, port))
+					.await?
+					.collect(),
+			)
+		})
+	}
+}
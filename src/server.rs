@@ -0,0 +1,220 @@
+use std::{io, str};
+
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Framed, FramedParts};
+use websocket_codec::ClientRequest;
+
+use crate::{Error, MessageCodec, Result};
+
+/// Largest HTTP request that is accepted during the opening handshake.
+const MAX_REQUEST_LEN: usize = 16 * 1024;
+
+/// Accepts WebSocket connections from clients.
+///
+/// The server reads the client's HTTP upgrade request, validates it and responds with `101 Switching Protocols`.
+/// Extensions such as `permessage-deflate` are never negotiated.
+#[derive(Clone, Debug, Default)]
+pub struct ServerBuilder {
+	subprotocols: Vec<String>,
+	headers: Vec<(String, String)>,
+}
+
+impl ServerBuilder {
+	/// Creates a `ServerBuilder` that supports no subprotocols and sends no extra headers.
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a subprotocol that the server supports.
+	///
+	/// If the client offers any of the supported subprotocols, the server selects the first one that was added. The
+	/// selected subprotocol is available from [`MessageCodec::subprotocol`].
+	pub fn add_subprotocol(
+		&mut self,
+		subprotocol: String,
+	) {
+		self.subprotocols.push(subprotocol);
+	}
+
+	/// Adds an extra HTTP header to the server's response.
+	pub fn add_header(
+		&mut self,
+		name: String,
+		value: String,
+	) {
+		self.headers.push((name, value));
+	}
+
+	/// Takes over a stream that a client has just connected on and performs the server side of the opening handshake.
+	///
+	/// This method assumes that the TLS connection has already been established, if needed. If the client's request
+	/// is not a valid WebSocket upgrade request, the server responds with `400 Bad Request`.
+	/// This method returns an `Err` result if the request is invalid or if reading from or writing to the stream fails.
+	/// On success it returns the stream framed with a server [`MessageCodec`].
+	pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+		&self,
+		mut stream: S,
+	) -> Result<Framed<S, MessageCodec>> {
+		let mut buf = BytesMut::with_capacity(1024);
+		let (request_len, result) = loop {
+			if stream.read_buf(&mut buf).await? == 0 {
+				return Err(io::Error::new(
+					io::ErrorKind::UnexpectedEof,
+					"client closed the connection during the HTTP request",
+				)
+				.into());
+			}
+
+			if let Some(parsed) = self.parse_request(&buf)? {
+				break parsed;
+			}
+
+			if buf.len() >= MAX_REQUEST_LEN {
+				return Err(Error::InvalidHandshake(
+					"client sent an HTTP request that is too long".to_owned(),
+				));
+			}
+		};
+
+		let (ws_accept, subprotocol) = match result {
+			Ok(accepted) => accepted,
+			Err(err) => {
+				stream
+					.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\nContent-Length: 0\r\n\r\n")
+					.await?;
+
+				return Err(err);
+			}
+		};
+
+		let mut response = String::new();
+		writeok!(
+			response,
+			"HTTP/1.1 101 Switching Protocols\r\n"
+		);
+		writeok!(
+			response,
+			"Upgrade: websocket\r\n"
+		);
+		writeok!(
+			response,
+			"Connection: Upgrade\r\n"
+		);
+		writeok!(
+			response,
+			"Sec-WebSocket-Accept: {ws_accept}\r\n"
+		);
+		if let Some(subprotocol) = &subprotocol {
+			writeok!(
+				response,
+				"Sec-WebSocket-Protocol: {subprotocol}\r\n"
+			);
+		}
+
+		for (name, value) in &self.headers {
+			writeok!(
+				response,
+				"{name}: {value}\r\n"
+			);
+		}
+
+		writeok!(response, "\r\n");
+		stream.write_all(response.as_bytes()).await?;
+
+		let mut codec = MessageCodec::server();
+		if let Some(subprotocol) = subprotocol {
+			codec = codec.with_subprotocol(subprotocol);
+		}
+
+		// Keep any frames that the client sent straight after its request
+		let _ = buf.split_to(request_len);
+		let mut parts = FramedParts::new::<websocket_codec::Message>(stream, codec);
+		parts.read_buf = buf;
+		Ok(Framed::from_parts(parts))
+	}
+
+	/// Parses the client's request once it's complete.
+	///
+	/// The outer `Result` fails when the data is not HTTP at all; the inner one fails when it's an HTTP request that
+	/// is not a valid WebSocket upgrade, which the server answers with an error response.
+	#[allow(clippy::type_complexity)]
+	fn parse_request(
+		&self,
+		data: &[u8],
+	) -> Result<
+		Option<(
+			usize,
+			Result<(String, Option<String>)>,
+		)>,
+	> {
+		let mut headers = [httparse::EMPTY_HEADER; 64];
+		let mut request = httparse::Request::new(&mut headers);
+		let status = request.parse(data).map_err(|err| {
+			Error::InvalidHandshake(format!(
+				"client sent invalid HTTP: {err}"
+			))
+		})?;
+
+		let httparse::Status::Complete(request_len) = status else {
+			return Ok(None);
+		};
+
+		let header = |name: &str| {
+			request
+				.headers
+				.iter()
+				.find(|header| header.name.eq_ignore_ascii_case(name))
+				.and_then(|header| str::from_utf8(header.value).ok())
+		};
+
+		let result = if request.method == Some("GET") {
+			ClientRequest::parse(header).map(|client_request| {
+				let subprotocol = self.select_subprotocol(request.headers);
+				(
+					client_request.ws_accept(),
+					subprotocol,
+				)
+			})
+		} else {
+			Err(Error::InvalidHandshake(
+				format!(
+					"client sent {method} request instead of GET",
+					method = request.method.unwrap_or_default()
+				),
+			))
+		};
+
+		Ok(Some((request_len, result)))
+	}
+
+	fn select_subprotocol(
+		&self,
+		headers: &[httparse::Header<'_>],
+	) -> Option<String> {
+		let offered: Vec<&str> = headers
+			.iter()
+			.filter(|header| {
+				header
+					.name
+					.eq_ignore_ascii_case("Sec-WebSocket-Protocol")
+			})
+			.filter_map(|header| str::from_utf8(header.value).ok())
+			.flat_map(|value| value.split(','))
+			.map(str::trim)
+			.collect();
+
+		self.subprotocols
+			.iter()
+			.find(|subprotocol| offered.contains(&subprotocol.as_str()))
+			.cloned()
+	}
+}
+
+/// Performs the server side of the opening handshake with default settings.
+/// This function returns an `Err` result if the request is invalid or if reading from or writing to the stream fails.
+/// See [`ServerBuilder::accept`].
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(stream: S) -> Result<Framed<S, MessageCodec>> {
+	ServerBuilder::new().accept(stream).await
+}
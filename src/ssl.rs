@@ -1,12 +1,17 @@
 use std::fmt::{Debug, Formatter};
+use std::io::{Read, Write};
+use std::net::TcpStream;
 use std::pin::Pin;
+#[cfg(feature = "__rustls")]
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::{fmt, io};
 
+use sha2::{Digest, Sha256};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream as TokioTcpStream;
 
-use crate::Result;
+use crate::{Error, Result};
 
 /// A reusable TLS connector for wrapping streams.
 #[derive(Clone)]
@@ -14,7 +19,11 @@ pub enum Connector {
 	/// Plain (non-TLS) connector.
 	Plain,
 	/// `native-tls` TLS connector.
+	#[cfg(feature = "native-tls")]
 	NativeTls(native_tls::TlsConnector),
+	/// `rustls` TLS connector.
+	#[cfg(feature = "__rustls")]
+	Rustls(Arc<rustls::ClientConfig>),
 }
 
 impl Debug for Connector {
@@ -24,7 +33,10 @@ impl Debug for Connector {
 	) -> fmt::Result {
 		match self {
 			Self::Plain => f.write_str("Connector::Plain"),
+			#[cfg(feature = "native-tls")]
 			Self::NativeTls(connector) => connector.fmt(f),
+			#[cfg(feature = "__rustls")]
+			Self::Rustls(config) => config.fmt(f),
 		}
 	}
 }
@@ -35,7 +47,19 @@ pub enum AsyncConnector {
 	/// Plain (non-TLS) connector.
 	Plain,
 	/// `native-tls` async TLS connector.
+	#[cfg(feature = "native-tls")]
 	NativeTls(tokio_native_tls::TlsConnector),
+	/// `rustls` async TLS connector.
+	#[cfg(feature = "__rustls")]
+	Rustls(tokio_rustls::TlsConnector),
+	/// Connector that checks the server's public key after the TLS handshake.
+	///
+	/// The connection is refused unless the SHA-256 hash of the server certificate's DER-encoded
+	/// `SubjectPublicKeyInfo` is one of the pinned hashes. See [`TlsBuilder::add_pinned_key`](crate::TlsBuilder::add_pinned_key).
+	Pinned(
+		Box<AsyncConnector>,
+		Vec<[u8; 32]>,
+	),
 }
 
 impl Debug for AsyncConnector {
@@ -45,15 +69,79 @@ impl Debug for AsyncConnector {
 	) -> fmt::Result {
 		match self {
 			Self::Plain => f.write_str("AsyncConnector::Plain"),
+			#[cfg(feature = "native-tls")]
 			Self::NativeTls(connector) => connector.fmt(f),
+			#[cfg(feature = "__rustls")]
+			Self::Rustls(_) => f.write_str("AsyncConnector::Rustls"),
+			Self::Pinned(connector, _) => f
+				.debug_tuple("AsyncConnector::Pinned")
+				.field(connector)
+				.finish(),
 		}
 	}
 }
 
-// #[allow(clippy::large_enum_variant)]
+#[allow(clippy::large_enum_variant)]
+enum MaybeTlsStreamInner {
+	Plain(TcpStream),
+	#[cfg(feature = "native-tls")]
+	NativeTls(native_tls::TlsStream<TcpStream>),
+	#[cfg(feature = "__rustls")]
+	Rustls(rustls::StreamOwned<rustls::ClientConnection, TcpStream>),
+}
+
+/// A stream that might be protected with TLS.
+pub struct MaybeTlsStream {
+	inner: MaybeTlsStreamInner,
+}
+
+impl Read for MaybeTlsStream {
+	fn read(
+		&mut self,
+		buf: &mut [u8],
+	) -> io::Result<usize> {
+		match &mut self.inner {
+			MaybeTlsStreamInner::Plain(s) => s.read(buf),
+			#[cfg(feature = "native-tls")]
+			MaybeTlsStreamInner::NativeTls(s) => s.read(buf),
+			#[cfg(feature = "__rustls")]
+			MaybeTlsStreamInner::Rustls(s) => s.read(buf),
+		}
+	}
+}
+
+impl Write for MaybeTlsStream {
+	fn write(
+		&mut self,
+		buf: &[u8],
+	) -> io::Result<usize> {
+		match &mut self.inner {
+			MaybeTlsStreamInner::Plain(s) => s.write(buf),
+			#[cfg(feature = "native-tls")]
+			MaybeTlsStreamInner::NativeTls(s) => s.write(buf),
+			#[cfg(feature = "__rustls")]
+			MaybeTlsStreamInner::Rustls(s) => s.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match &mut self.inner {
+			MaybeTlsStreamInner::Plain(s) => s.flush(),
+			#[cfg(feature = "native-tls")]
+			MaybeTlsStreamInner::NativeTls(s) => s.flush(),
+			#[cfg(feature = "__rustls")]
+			MaybeTlsStreamInner::Rustls(s) => s.flush(),
+		}
+	}
+}
+
+#[allow(clippy::large_enum_variant)]
 enum AsyncMaybeTlsStreamInner {
 	Plain(TokioTcpStream),
+	#[cfg(feature = "native-tls")]
 	NativeTls(tokio_native_tls::TlsStream<TokioTcpStream>),
+	#[cfg(feature = "__rustls")]
+	Rustls(tokio_rustls::client::TlsStream<TokioTcpStream>),
 }
 
 /// An async stream that might be protected with TLS.
@@ -61,6 +149,29 @@ pub struct AsyncMaybeTlsStream {
 	inner: AsyncMaybeTlsStreamInner,
 }
 
+impl AsyncMaybeTlsStream {
+	/// Returns the DER encoding of the server's end-entity certificate.
+	fn peer_certificate(&self) -> Option<Vec<u8>> {
+		match &self.inner {
+			AsyncMaybeTlsStreamInner::Plain(_) => None,
+			#[cfg(feature = "native-tls")]
+			AsyncMaybeTlsStreamInner::NativeTls(s) => s
+				.get_ref()
+				.peer_certificate()
+				.ok()
+				.flatten()
+				.and_then(|cert| cert.to_der().ok()),
+			#[cfg(feature = "__rustls")]
+			AsyncMaybeTlsStreamInner::Rustls(s) => s
+				.get_ref()
+				.1
+				.peer_certificates()
+				.and_then(|certs| certs.first())
+				.map(|cert| cert.to_vec()),
+		}
+	}
+}
+
 impl AsyncRead for AsyncMaybeTlsStream {
 	fn poll_read(
 		self: Pin<&mut Self>,
@@ -69,7 +180,10 @@ impl AsyncRead for AsyncMaybeTlsStream {
 	) -> Poll<io::Result<()>> {
 		match &mut self.get_mut().inner {
 			AsyncMaybeTlsStreamInner::Plain(ref mut s) => Pin::new(s).poll_read(cx, buf),
+			#[cfg(feature = "native-tls")]
 			AsyncMaybeTlsStreamInner::NativeTls(s) => Pin::new(s).poll_read(cx, buf),
+			#[cfg(feature = "__rustls")]
+			AsyncMaybeTlsStreamInner::Rustls(s) => Pin::new(s).poll_read(cx, buf),
 		}
 	}
 }
@@ -82,7 +196,10 @@ impl AsyncWrite for AsyncMaybeTlsStream {
 	) -> Poll<io::Result<usize>> {
 		match &mut self.get_mut().inner {
 			AsyncMaybeTlsStreamInner::Plain(ref mut s) => Pin::new(s).poll_write(cx, buf),
+			#[cfg(feature = "native-tls")]
 			AsyncMaybeTlsStreamInner::NativeTls(s) => Pin::new(s).poll_write(cx, buf),
+			#[cfg(feature = "__rustls")]
+			AsyncMaybeTlsStreamInner::Rustls(s) => Pin::new(s).poll_write(cx, buf),
 		}
 	}
 
@@ -92,7 +209,10 @@ impl AsyncWrite for AsyncMaybeTlsStream {
 	) -> Poll<io::Result<()>> {
 		match &mut self.get_mut().inner {
 			AsyncMaybeTlsStreamInner::Plain(ref mut s) => Pin::new(s).poll_flush(cx),
+			#[cfg(feature = "native-tls")]
 			AsyncMaybeTlsStreamInner::NativeTls(s) => Pin::new(s).poll_flush(cx),
+			#[cfg(feature = "__rustls")]
+			AsyncMaybeTlsStreamInner::Rustls(s) => Pin::new(s).poll_flush(cx),
 		}
 	}
 
@@ -102,35 +222,217 @@ impl AsyncWrite for AsyncMaybeTlsStream {
 	) -> Poll<io::Result<()>> {
 		match &mut self.get_mut().inner {
 			AsyncMaybeTlsStreamInner::Plain(ref mut s) => Pin::new(s).poll_shutdown(cx),
+			#[cfg(feature = "native-tls")]
 			AsyncMaybeTlsStreamInner::NativeTls(s) => Pin::new(s).poll_shutdown(cx),
+			#[cfg(feature = "__rustls")]
+			AsyncMaybeTlsStreamInner::Rustls(s) => Pin::new(s).poll_shutdown(cx),
+		}
+	}
+}
+
+#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+pub(crate) fn tls_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> Error {
+	Error::Tls(Box::new(err))
+}
+
+#[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+fn no_tls_backend<T>() -> Result<T> {
+	Err(Error::Tls(
+		"no TLS backend is enabled; enable the `native-tls`, `rustls-tls-native-roots` or `rustls-tls-webpki-roots` \
+		 feature"
+			.into(),
+	))
+}
+
+/// Returns the root certificates selected in the feature flags.
+#[cfg(feature = "__rustls")]
+pub(crate) fn rustls_roots() -> Result<rustls::RootCertStore> {
+	let mut roots = rustls::RootCertStore::empty();
+
+	#[cfg(feature = "rustls-tls-native-roots")]
+	{
+		let native = rustls_native_certs::load_native_certs();
+		if native.certs.is_empty() {
+			if let Some(err) = native.errors.into_iter().next() {
+				return Err(tls_error(err));
+			}
 		}
+
+		roots.add_parsable_certificates(native.certs);
+	}
+
+	#[cfg(feature = "rustls-tls-webpki-roots")]
+	roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+	Ok(roots)
+}
+
+/// Starts a `rustls` configuration that uses the `ring` crypto provider, regardless of the process-wide default.
+#[cfg(feature = "__rustls")]
+pub(crate) fn rustls_config_builder(
+	provider: Arc<rustls::crypto::CryptoProvider>
+) -> Result<rustls::ConfigBuilder<rustls::ClientConfig, rustls::WantsVerifier>> {
+	rustls::ClientConfig::builder_with_provider(provider)
+		.with_safe_default_protocol_versions()
+		.map_err(tls_error)
+}
+
+/// Builds a `rustls` configuration that trusts the root certificates selected in the feature flags.
+#[cfg(feature = "__rustls")]
+fn rustls_config() -> Result<Arc<rustls::ClientConfig>> {
+	let config = rustls_config_builder(Arc::new(
+		rustls::crypto::ring::default_provider(),
+	))?
+	.with_root_certificates(rustls_roots()?)
+	.with_no_client_auth();
+
+	Ok(Arc::new(config))
+}
+
+/// Splits the first DER element off `data`, returning the whole element, its contents and whatever follows it.
+fn der_element(data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+	let first_len = *data.get(1)?;
+	let (header_len, len) = if first_len < 0x80 {
+		(2, usize::from(first_len))
+	} else {
+		let len_len = usize::from(first_len & 0x7f);
+		if len_len == 0 || len_len > 4 {
+			return None;
+		}
+
+		let len_bytes = data.get(2..2 + len_len)?;
+		(
+			2 + len_len,
+			len_bytes.iter().fold(0, |len, &b| {
+				len << 8 | usize::from(b)
+			}),
+		)
+	};
+
+	let element = data.get(..header_len.checked_add(len)?)?;
+	Some((
+		element,
+		&element[header_len..],
+		&data[element.len()..],
+	))
+}
+
+/// Returns the DER-encoded `SubjectPublicKeyInfo` of a DER-encoded X.509 certificate.
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+	let (_, certificate, _) = der_element(cert)?;
+	let (_, tbs_certificate, _) = der_element(certificate)?;
+
+	// The version is optional and tagged [0]
+	let mut fields = tbs_certificate;
+	if fields.first() == Some(&0xa0) {
+		fields = der_element(fields)?.2;
+	}
+
+	// Skip the serial number, signature algorithm, issuer, validity and subject
+	for _ in 0..5 {
+		fields = der_element(fields)?.2;
 	}
+
+	Some(der_element(fields)?.0)
+}
+
+fn check_pinned_key(
+	stream: &AsyncMaybeTlsStream,
+	pins: &[[u8; 32]],
+) -> Result<()> {
+	let cert = stream
+		.peer_certificate()
+		.ok_or_else(|| Error::Tls("server did not send a certificate to check against the pinned keys".into()))?;
+
+	let spki = subject_public_key_info(&cert)
+		.ok_or_else(|| Error::Tls("server sent a certificate that could not be parsed".into()))?;
+	let hash: [u8; 32] = Sha256::digest(spki).into();
+	if pins.contains(&hash) {
+		Ok(())
+	} else {
+		Err(Error::Tls(
+			"server's public key does not match any pinned key".into(),
+		))
+	}
+}
+
+#[cfg(feature = "__rustls")]
+fn server_name(domain: &str) -> Result<rustls::pki_types::ServerName<'static>> {
+	rustls::pki_types::ServerName::try_from(domain.to_owned()).map_err(tls_error)
 }
 
 impl Connector {
 	/// Creates a new `Connector` with the underlying TLS library specified in the feature flags.
-	/// This method returns an `Err` when creating the underlying TLS connector fails.
-	// #[allow(clippy::unnecessary_wraps)]
+	///
+	/// `rustls` is preferred when both it and `native-tls` are enabled.
+	/// This method returns an `Err` when creating the underlying TLS connector fails, or when no TLS feature is
+	/// enabled.
 	pub fn new_with_default_tls_config() -> Result<Self> {
-		Ok(Self::NativeTls(
-			native_tls::TlsConnector::new()?,
-		))
+		#[cfg(feature = "__rustls")]
+		let connector = Self::Rustls(rustls_config()?);
+		#[cfg(all(feature = "native-tls", not(feature = "__rustls")))]
+		let connector = Self::NativeTls(native_tls::TlsConnector::new().map_err(tls_error)?);
+		#[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+		let connector = no_tls_backend()?;
+		Ok(connector)
+	}
+
+	#[cfg_attr(not(any(feature = "native-tls", feature = "__rustls")), allow(unused_variables))]
+	pub(crate) fn wrap(
+		self,
+		domain: &str,
+		stream: TcpStream,
+	) -> Result<MaybeTlsStream> {
+		let inner = match self {
+			Self::Plain => MaybeTlsStreamInner::Plain(stream),
+			#[cfg(feature = "native-tls")]
+			Self::NativeTls(connector) => MaybeTlsStreamInner::NativeTls(
+				connector
+					.connect(domain, stream)
+					.map_err(tls_error)?,
+			),
+			#[cfg(feature = "__rustls")]
+			Self::Rustls(config) => {
+				let mut connection = rustls::ClientConnection::new(config, server_name(domain)?).map_err(tls_error)?;
+				let mut stream = stream;
+				// Finish the handshake here so that certificate errors are reported by `connect`
+				while connection.is_handshaking() {
+					connection
+						.complete_io(&mut stream)
+						.map_err(tls_error)?;
+				}
+
+				MaybeTlsStreamInner::Rustls(rustls::StreamOwned::new(
+					connection, stream,
+				))
+			}
+		};
+
+		Ok(MaybeTlsStream { inner })
 	}
 }
 
 impl AsyncConnector {
 	/// Creates a new async `Connector` with the underlying TLS library specified in the feature flags.
-	/// This method returns an `Err` when creating the underlying TLS connector fails.
-	// #[allow(clippy::unnecessary_wraps)]
+	///
+	/// `rustls` is preferred when both it and `native-tls` are enabled.
+	/// This method returns an `Err` when creating the underlying TLS connector fails, or when no TLS feature is
+	/// enabled.
 	pub fn new_with_default_tls_config() -> Result<Self> {
-		Ok(Self::NativeTls(
-			native_tls::TlsConnector::new()?.into(),
-		))
+		#[cfg(feature = "__rustls")]
+		let connector = Self::Rustls(rustls_config()?.into());
+		#[cfg(all(feature = "native-tls", not(feature = "__rustls")))]
+		let connector = Self::NativeTls(
+			native_tls::TlsConnector::new()
+				.map_err(tls_error)?
+				.into(),
+		);
+		#[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+		let connector = no_tls_backend()?;
+		Ok(connector)
 	}
 
-	// #[allow(clippy::match_wildcard_for_single_variants)]
-	// #[allow(clippy::unnecessary_wraps)]
-	// #[allow(unused_variables)]
+	#[cfg_attr(not(any(feature = "native-tls", feature = "__rustls")), allow(unused_variables))]
 	pub(crate) async fn wrap(
 		self,
 		domain: &str,
@@ -138,9 +440,136 @@ impl AsyncConnector {
 	) -> Result<AsyncMaybeTlsStream> {
 		let inner = match self {
 			Self::Plain => AsyncMaybeTlsStreamInner::Plain(stream),
-			Self::NativeTls(connector) => AsyncMaybeTlsStreamInner::NativeTls(connector.connect(domain, stream).await?),
+			#[cfg(feature = "native-tls")]
+			Self::NativeTls(connector) => AsyncMaybeTlsStreamInner::NativeTls(
+				connector
+					.connect(domain, stream)
+					.await
+					.map_err(tls_error)?,
+			),
+			#[cfg(feature = "__rustls")]
+			Self::Rustls(connector) => AsyncMaybeTlsStreamInner::Rustls(
+				connector
+					.connect(server_name(domain)?, stream)
+					.await
+					.map_err(tls_error)?,
+			),
+			Self::Pinned(connector, pins) => {
+				let stream = Box::pin(connector.wrap(domain, stream)).await?;
+				check_pinned_key(&stream, &pins)?;
+				return Ok(stream);
+			}
 		};
 
 		Ok(AsyncMaybeTlsStream { inner })
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const RSA_V3: &[u8] = include_bytes!("../tests/data/rsa_v3.der");
+	const EC_V1: &[u8] = include_bytes!("../tests/data/ec_v1.der");
+
+	/// Hashes the public key the way `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst
+	/// -sha256` does.
+	fn spki_sha256(cert: &[u8]) -> Option<String> {
+		let spki = subject_public_key_info(cert)?;
+		Some(
+			Sha256::digest(spki)
+				.iter()
+				.map(|b| format!("{b:02x}"))
+				.collect(),
+		)
+	}
+
+	#[test]
+	fn spki_of_v3_certificate() {
+		// The version is present, and lengths take two bytes
+		assert_eq!(&RSA_V3[..2], [0x30, 0x82]);
+		assert_eq!(RSA_V3[8], 0xa0);
+		assert_eq!(
+			spki_sha256(RSA_V3).as_deref(),
+			Some("ee066fe719bc7e002a0ccdda2078ae1e96b842d04ca87281cdb6f24e4655557e")
+		);
+	}
+
+	#[test]
+	fn spki_of_v1_certificate() {
+		// The version is left out, and the TBS certificate's length takes one byte
+		assert_eq!(&EC_V1[4..6], [0x30, 0x81]);
+		assert_eq!(EC_V1[7], 0x02);
+		assert_eq!(
+			spki_sha256(EC_V1).as_deref(),
+			Some("a191d4c4b4da977952fb30b26b415b3d95ae4acc5ef2ddf123eac8d9cd4b4e4e")
+		);
+	}
+
+	#[test]
+	fn spki_of_truncated_certificate() {
+		for cert in [RSA_V3, EC_V1] {
+			for len in 0..cert.len() {
+				assert_eq!(
+					subject_public_key_info(&cert[..len]),
+					None,
+					"{len} bytes"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn spki_of_garbage() {
+		assert_eq!(
+			subject_public_key_info(b"not a certificate"),
+			None
+		);
+
+		// A certificate whose TBS certificate holds fewer than seven fields
+		assert_eq!(
+			subject_public_key_info(&[0x30, 0x06, 0x30, 0x04, 0x02, 0x00, 0x02, 0x00]),
+			None
+		);
+	}
+
+	#[test]
+	fn der_lengths() {
+		assert_eq!(
+			der_element(&[0x02, 0x01, 0x05, 0xff]),
+			Some((
+				&[0x02, 0x01, 0x05][..],
+				&[0x05][..],
+				&[0xff][..]
+			))
+		);
+		assert_eq!(
+			der_element(&[0x04, 0x81, 0x01, 0x07]),
+			Some((
+				&[0x04, 0x81, 0x01, 0x07][..],
+				&[0x07][..],
+				&[][..]
+			))
+		);
+
+		// Indefinite lengths, lengths of more than four bytes, and contents that run past the end
+		assert_eq!(
+			der_element(&[0x30, 0x80, 0x00, 0x00]),
+			None
+		);
+		assert_eq!(
+			der_element(&[0x04, 0x85, 0, 0, 0, 0, 1, 0]),
+			None
+		);
+		assert_eq!(
+			der_element(&[0x04, 0x82, 0x01]),
+			None
+		);
+		assert_eq!(
+			der_element(&[0x04, 0x02, 0x00]),
+			None
+		);
+		assert_eq!(der_element(&[0x04]), None);
+		assert_eq!(der_element(&[]), None);
+	}
+}
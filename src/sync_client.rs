@@ -0,0 +1,102 @@
+use std::io::{self, Read, Write};
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{Message, MessageCodec, Result};
+
+/// Reads whatever is available from `reader` into `buf`, returning the number of bytes read.
+pub(crate) fn read_into<R: Read>(
+	reader: &mut R,
+	buf: &mut BytesMut,
+) -> io::Result<usize> {
+	let mut chunk = [0; 8192];
+	let n = reader.read(&mut chunk)?;
+	buf.extend_from_slice(&chunk[..n]);
+	Ok(n)
+}
+
+/// Sends and receives WebSocket messages over a blocking stream.
+///
+/// Unlike [`AsyncClient`](crate::AsyncClient), this client doesn't take part in the closing handshake or answer
+/// pings on its own.
+pub struct Client<S> {
+	stream: S,
+	codec: MessageCodec,
+	read_buf: BytesMut,
+	write_buf: BytesMut,
+}
+
+impl<S: Read + Write> Client<S> {
+	/// Wraps a stream that has completed the opening handshake.
+	///
+	/// `read_buf` holds any data that was read from the stream after the end of the handshake.
+	#[must_use]
+	pub fn new(
+		stream: S,
+		codec: MessageCodec,
+		read_buf: BytesMut,
+	) -> Self {
+		Self {
+			stream,
+			codec,
+			read_buf,
+			write_buf: BytesMut::new(),
+		}
+	}
+
+	/// Returns a reference to the underlying stream.
+	#[must_use]
+	pub fn get_ref(&self) -> &S {
+		&self.stream
+	}
+
+	/// Returns a mutable reference to the underlying stream.
+	///
+	/// Reading from or writing to the stream directly is likely to corrupt the WebSocket connection.
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.stream
+	}
+
+	/// Returns a reference to the codec, for instance to look up the negotiated subprotocol.
+	#[must_use]
+	pub fn codec(&self) -> &MessageCodec {
+		&self.codec
+	}
+
+	/// Sends a message and flushes it to the stream.
+	/// This method returns an `Err` result if encoding the message or writing to the stream fails.
+	pub fn send(
+		&mut self,
+		message: &Message,
+	) -> Result<()> {
+		self.codec.encode(message, &mut self.write_buf)?;
+		let result = self.stream.write_all(&self.write_buf);
+		self.write_buf.clear();
+		result?;
+		Ok(self.stream.flush()?)
+	}
+
+	/// Blocks until a complete message has been received.
+	/// This method returns an `Err` result if the server sends invalid data, or if reading from the stream fails or
+	/// reaches the end of the stream.
+	pub fn receive(&mut self) -> Result<Message> {
+		loop {
+			if let Some(message) = self.codec.decode(&mut self.read_buf)? {
+				return Ok(message);
+			}
+
+			if read_into(
+				&mut self.stream,
+				&mut self.read_buf,
+			)? == 0
+			{
+				return Err(io::Error::new(
+					io::ErrorKind::UnexpectedEof,
+					"server closed the connection",
+				)
+				.into());
+			}
+		}
+	}
+}
@@ -0,0 +1,842 @@
+use std::fmt::Write;
+
+use bytes::{
+	Bytes,
+	BytesMut,
+};
+use flate2::{
+	Compress,
+	Compression,
+	Decompress,
+	FlushCompress,
+	FlushDecompress,
+	Status,
+};
+
+use crate::extension::WebSocketExtension;
+use crate::frame::FrameHeader;
+use crate::opcode::Opcode;
+use crate::{
+	Error,
+	ProtocolError,
+	Result,
+};
+
+const RSV1: u8 = 0x40;
+
+const EXTENSION_NAME: &str = "permessage-deflate";
+const TRAILER: [u8; 4] = [0, 0, 0xff, 0xff];
+const MAX_WINDOW_BITS: u8 = 15;
+
+/// Parameters offered to the server for the `permessage-deflate` extension, as defined by
+/// [RFC7692](https://tools.ietf.org/html/rfc7692).
+#[derive(Clone, Debug)]
+pub struct DeflateConfig {
+	client_no_context_takeover: bool,
+	server_no_context_takeover: bool,
+	client_max_window_bits: Option<u8>,
+	server_max_window_bits: Option<u8>,
+	compress: bool,
+}
+
+impl Default for DeflateConfig {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl DeflateConfig {
+	/// Returns a `DeflateConfig` that offers `permessage-deflate` with no extra parameters and compresses
+	/// outgoing text and binary messages.
+	#[must_use]
+	pub fn new() -> Self {
+		Self {
+			client_no_context_takeover: false,
+			server_no_context_takeover: false,
+			client_max_window_bits: None,
+			server_max_window_bits: None,
+			compress: true,
+		}
+	}
+
+	/// Asks the server to allow the client to reset its compression context after every message.
+	pub fn set_client_no_context_takeover(
+		&mut self,
+		value: bool,
+	) {
+		self.client_no_context_takeover = value;
+	}
+
+	/// Asks the server to reset its compression context after every message.
+	pub fn set_server_no_context_takeover(
+		&mut self,
+		value: bool,
+	) {
+		self.server_no_context_takeover = value;
+	}
+
+	/// Limits the LZ77 window used by the client to compress messages, between 8 and 15 bits.
+	///
+	/// # Panics
+	///
+	/// Panics if `bits` is outside the range 8 to 15.
+	pub fn set_client_max_window_bits(
+		&mut self,
+		bits: u8,
+	) {
+		assert!(
+			(8..=MAX_WINDOW_BITS).contains(&bits),
+			"window bits must be within 8..=15"
+		);
+		self.client_max_window_bits = Some(bits);
+	}
+
+	/// Asks the server to limit the LZ77 window it uses to compress messages, between 8 and 15 bits.
+	///
+	/// # Panics
+	///
+	/// Panics if `bits` is outside the range 8 to 15.
+	pub fn set_server_max_window_bits(
+		&mut self,
+		bits: u8,
+	) {
+		assert!(
+			(8..=MAX_WINDOW_BITS).contains(&bits),
+			"window bits must be within 8..=15"
+		);
+		self.server_max_window_bits = Some(bits);
+	}
+
+	/// Sets whether outgoing text and binary messages are compressed.
+	///
+	/// Incoming compressed messages are always decompressed once the extension has been negotiated.
+	pub fn set_compress(
+		&mut self,
+		value: bool,
+	) {
+		self.compress = value;
+	}
+}
+
+/// The `permessage-deflate` extension, as negotiated on a single connection.
+///
+/// Created from a [`DeflateConfig`] and passed to the [`UpgradeCodec`](crate::UpgradeCodec) and then the
+/// [`MessageCodec`](crate::MessageCodec) as a [`WebSocketExtension`]. Messages flagged with the RSV1 bit are
+/// decompressed, and outgoing text and binary messages are compressed if the configuration allows it.
+#[allow(clippy::struct_excessive_bools)]
+pub struct PerMessageDeflate {
+	config: DeflateConfig,
+	compress: Option<Compress>,
+	decompress: Decompress,
+	reset_compress: bool,
+	reset_decompress: bool,
+	compressing: bool,
+	decompressing: bool,
+	stream_ended: bool,
+}
+
+impl PerMessageDeflate {
+	/// Returns a `PerMessageDeflate` object that has not been negotiated yet.
+	#[must_use]
+	pub fn new(config: DeflateConfig) -> Self {
+		Self {
+			config,
+			compress: None,
+			decompress: Decompress::new(false),
+			reset_compress: false,
+			reset_decompress: false,
+			compressing: false,
+			decompressing: false,
+			stream_ended: false,
+		}
+	}
+
+	#[allow(clippy::cast_possible_truncation)]
+	fn deflate(
+		compress: &mut Compress,
+		mut data: &[u8],
+	) -> Result<Vec<u8>> {
+		let mut buf = Vec::with_capacity(data.len() / 2 + 64);
+		loop {
+			if buf.len() == buf.capacity() {
+				buf.reserve(buf.capacity());
+			}
+
+			let total_in = compress.total_in();
+			compress
+				.compress_vec(
+					data,
+					&mut buf,
+					FlushCompress::Sync,
+				)
+				.map_err(|err| Error::Extension(Box::new(err)))?;
+			data = &data[(compress.total_in() - total_in) as usize..];
+
+			// A sync flush is complete once zlib stops filling the output buffer
+			if data.is_empty() && buf.len() < buf.capacity() {
+				return Ok(buf);
+			}
+		}
+	}
+
+	#[allow(clippy::cast_possible_truncation)]
+	fn inflate(
+		&mut self,
+		mut data: &[u8],
+		buf: &mut Vec<u8>,
+		max_len: Option<usize>,
+	) -> Result<()> {
+		// Nothing can follow a final deflate block within the same message
+		if self.stream_ended && !data.is_empty() {
+			return Err(ProtocolError::InvalidCompressedData.into());
+		}
+
+		loop {
+			if buf.len() == buf.capacity() {
+				buf.reserve(buf.capacity().max(64));
+			}
+
+			let total_in = self.decompress.total_in();
+			let total_out = self.decompress.total_out();
+			let status = self
+				.decompress
+				.decompress_vec(
+					data,
+					buf,
+					FlushDecompress::Sync,
+				)
+				.map_err(|_| ProtocolError::InvalidCompressedData)?;
+			let consumed = (self.decompress.total_in() - total_in) as usize;
+			data = &data[consumed..];
+
+			if let Some(limit) = max_len.filter(|&limit| buf.len() > limit) {
+				return Err(Error::MessageTooLarge {
+					size: buf.len() as u64,
+					limit: limit as u64,
+				});
+			}
+
+			if status == Status::StreamEnd {
+				// The peer ended the message with a BFINAL block (RFC 7692 section 7.2.3.3), so the next message
+				// starts a new deflate stream
+				self.decompress.reset(false);
+				self.stream_ended = true;
+				if !data.is_empty() {
+					return Err(ProtocolError::InvalidCompressedData.into());
+				}
+
+				return Ok(());
+			}
+
+			if buf.len() < buf.capacity()
+				&& (data.is_empty() || (consumed == 0 && self.decompress.total_out() == total_out))
+			{
+				return Ok(());
+			}
+		}
+	}
+}
+
+impl WebSocketExtension for PerMessageDeflate {
+	fn name(&self) -> &str {
+		EXTENSION_NAME
+	}
+
+	fn offer(&self) -> String {
+		let mut s = EXTENSION_NAME.to_owned();
+		if self.config.client_no_context_takeover {
+			s += "; client_no_context_takeover";
+		}
+
+		if self.config.server_no_context_takeover {
+			s += "; server_no_context_takeover";
+		}
+
+		if let Some(bits) = self.config.server_max_window_bits {
+			let _ = write!(
+				s,
+				"; server_max_window_bits={bits}"
+			);
+		}
+
+		// We can always honour a limit on the client window, so advertise it even when not configured
+		s += "; client_max_window_bits";
+		if let Some(bits) = self.config.client_max_window_bits {
+			let _ = write!(s, "={bits}");
+		}
+
+		s
+	}
+
+	fn accept(
+		&mut self,
+		params: &[(&str, Option<&str>)],
+	) -> Result<()> {
+		let mut client_no_context_takeover = false;
+		let mut server_no_context_takeover = false;
+		let mut client_max_window_bits = None;
+		let mut server_max_window_bits = false;
+
+		for &(name, value) in params {
+			match name {
+				"client_no_context_takeover" if value.is_none() && !client_no_context_takeover => {
+					client_no_context_takeover = true;
+				}
+				"server_no_context_takeover" if value.is_none() && !server_no_context_takeover => {
+					server_no_context_takeover = true;
+				}
+				"client_max_window_bits" if client_max_window_bits.is_none() => {
+					client_max_window_bits = Some(parse_window_bits(
+						name,
+						value,
+						self.config.client_max_window_bits,
+					)?);
+				}
+				"server_max_window_bits" if !server_max_window_bits => {
+					// We always decompress using the largest window, so the server's choice only needs to be valid
+					parse_window_bits(
+						name,
+						value,
+						self.config.server_max_window_bits,
+					)?;
+					server_max_window_bits = true;
+				}
+				_ => {
+					return Err(Error::InvalidHandshake(
+						format!("server responded with invalid {EXTENSION_NAME} parameter: {name}"),
+					));
+				}
+			}
+		}
+
+		let window_bits = client_max_window_bits
+			.or(self.config.client_max_window_bits)
+			.unwrap_or(MAX_WINDOW_BITS);
+
+		// zlib can't produce raw deflate streams with a 256-byte window.
+		// Sending uncompressed messages is always allowed, so that's what we do instead.
+		self.compress = if self.config.compress && window_bits > 8 {
+			Some(
+				Compress::new_with_window_bits(
+					Compression::default(),
+					false,
+					window_bits,
+				),
+			)
+		} else {
+			None
+		};
+
+		self.reset_compress = client_no_context_takeover || self.config.client_no_context_takeover;
+		self.reset_decompress = server_no_context_takeover;
+		Ok(())
+	}
+
+	fn rsv_bits(&self) -> u8 {
+		RSV1
+	}
+
+	fn encode_frame(
+		&mut self,
+		header: &mut FrameHeader,
+		data: Bytes,
+	) -> Result<Bytes> {
+		match Opcode::try_from(header.opcode()) {
+			Some(opcode) if opcode.is_control() => return Ok(data),
+			Some(_) => self.compressing = self.compress.is_some(),
+			None => {}
+		}
+
+		let Some(compress) = self
+			.compress
+			.as_mut()
+			.filter(|_| self.compressing)
+		else {
+			return Ok(data);
+		};
+
+		if header.opcode() != 0 {
+			header.set_rsv(header.rsv() | RSV1);
+		}
+
+		let mut buf = Self::deflate(compress, &data)?;
+		if header.fin() {
+			if buf.ends_with(&TRAILER) {
+				buf.truncate(buf.len() - TRAILER.len());
+			}
+
+			if self.reset_compress {
+				compress.reset();
+			}
+
+			self.compressing = false;
+		}
+
+		Ok(buf.into())
+	}
+
+	fn decode_frame(
+		&mut self,
+		header: &FrameHeader,
+		data: BytesMut,
+		max_len: Option<usize>,
+	) -> Result<BytesMut> {
+		let compressed = header.rsv() & RSV1 != 0;
+		match Opcode::try_from(header.opcode()) {
+			Some(opcode) if opcode.is_control() => {
+				if compressed {
+					return Err(ProtocolError::InvalidExtensionBits("control frames must not be compressed").into());
+				}
+
+				return Ok(data);
+			}
+			Some(_) => self.decompressing = compressed,
+			None => {
+				if compressed {
+					return Err(
+						ProtocolError::InvalidExtensionBits("continuation frames must not have the RSV1 bit set")
+							.into(),
+					);
+				}
+			}
+		}
+
+		if !self.decompressing {
+			return Ok(data);
+		}
+
+		let mut buf = Vec::with_capacity(data.len() * 2 + 64);
+		self.inflate(&data, &mut buf, max_len)?;
+
+		if header.fin() {
+			if self.stream_ended {
+				self.stream_ended = false;
+			} else {
+				self.inflate(&TRAILER, &mut buf, max_len)?;
+
+				if self.reset_decompress {
+					self.decompress.reset(false);
+				}
+			}
+
+			self.decompressing = false;
+		}
+
+		Ok(buf.as_slice().into())
+	}
+}
+
+fn parse_window_bits(
+	name: &str,
+	value: Option<&str>,
+	offered: Option<u8>,
+) -> Result<u8> {
+	let bits = value
+		.and_then(|value| value.parse::<u8>().ok())
+		.filter(|bits| (8..=MAX_WINDOW_BITS).contains(bits))
+		.ok_or_else(|| {
+			Error::InvalidHandshake(format!(
+				"server responded with invalid {EXTENSION_NAME} {name}: {value:?}"
+			))
+		})?;
+
+	if bits > offered.unwrap_or(MAX_WINDOW_BITS) {
+		return Err(Error::InvalidHandshake(
+			format!("server responded with {EXTENSION_NAME} {name}={bits}, larger than offered"),
+		));
+	}
+
+	Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use bytes::{
+		BufMut,
+		BytesMut,
+	};
+	use flate2::write::DeflateEncoder;
+	use flate2::Compression;
+	use tokio_util::codec::{
+		Decoder,
+		Encoder,
+	};
+
+	use super::*;
+	use crate::{
+		Message,
+		MessageCodec,
+	};
+
+	fn negotiated(params: &[(&str, Option<&str>)]) -> Box<PerMessageDeflate> {
+		let mut extension = PerMessageDeflate::new(DeflateConfig::new());
+		extension.accept(params).unwrap();
+		Box::new(extension)
+	}
+
+	/// Returns a client codec that compresses and a server codec that decompresses, negotiated with the same
+	/// parameters.
+	fn codec_pair(params: &[(&str, Option<&str>)]) -> (MessageCodec, MessageCodec) {
+		(
+			MessageCodec::client().with_extension(negotiated(params)),
+			MessageCodec::server().with_extension(negotiated(params)),
+		)
+	}
+
+	/// Encodes each message and decodes it again, returning the length of each message on the wire.
+	fn round_trip(
+		encoder: &mut MessageCodec,
+		decoder: &mut MessageCodec,
+		messages: &[Message],
+	) -> Vec<usize> {
+		messages
+			.iter()
+			.map(|message| {
+				let mut buf = BytesMut::new();
+				encoder.encode(message, &mut buf).unwrap();
+				let len = buf.len();
+				assert_eq!(
+					decoder.decode(&mut buf).unwrap().as_ref(),
+					Some(message)
+				);
+				assert!(buf.is_empty());
+				len
+			})
+			.collect()
+	}
+
+	/// Writes an unmasked frame, as sent by a server.
+	fn server_frame(
+		buf: &mut BytesMut,
+		fin: bool,
+		rsv: u8,
+		opcode: u8,
+		data: &[u8],
+	) {
+		FrameHeader::new(
+			fin,
+			rsv,
+			opcode,
+			None,
+			data.len().into(),
+		)
+		.write_to_bytes(buf);
+		buf.put_slice(data);
+	}
+
+	fn finished_stream(data: &[u8]) -> Vec<u8> {
+		let mut encoder = DeflateEncoder::new(
+			Vec::new(),
+			Compression::default(),
+		);
+		encoder.write_all(data).unwrap();
+		encoder.finish().unwrap()
+	}
+
+	#[test]
+	fn round_trip_with_context_takeover() {
+		let (mut encoder, mut decoder) = codec_pair(&[]);
+		let message = Message::text("hello, hello, hello, hello, hello world");
+		let lens = round_trip(
+			&mut encoder,
+			&mut decoder,
+			&[message.clone(), message.clone(), message],
+		);
+
+		// Later messages refer back to the earlier ones
+		assert!(lens[1] < lens[0]);
+	}
+
+	#[test]
+	fn round_trip_without_context_takeover() {
+		let (mut encoder, mut decoder) = codec_pair(&[
+			(
+				"client_no_context_takeover",
+				None,
+			),
+			(
+				"server_no_context_takeover",
+				None,
+			),
+		]);
+		let message = Message::text("hello, hello, hello, hello, hello world");
+		let lens = round_trip(
+			&mut encoder,
+			&mut decoder,
+			&[message.clone(), message.clone(), message],
+		);
+
+		assert_eq!(lens[0], lens[1]);
+		assert_eq!(lens[1], lens[2]);
+	}
+
+	#[test]
+	fn round_trip_with_small_window() {
+		let (mut encoder, mut decoder) = codec_pair(&[(
+			"client_max_window_bits",
+			Some("9"),
+		)]);
+		round_trip(
+			&mut encoder,
+			&mut decoder,
+			&[Message::binary(
+				(0..4096u32)
+					.map(|n| (n % 251) as u8)
+					.collect::<Vec<_>>(),
+			)],
+		);
+	}
+
+	#[test]
+	fn round_trip_fragmented() {
+		let (encoder, mut decoder) = codec_pair(&[]);
+		let mut encoder = encoder.with_max_outgoing_frame_size(7);
+		let text = "a fragmented message, a fragmented message, a fragmented message";
+		let mut buf = BytesMut::new();
+		encoder
+			.encode(Message::text(text), &mut buf)
+			.unwrap();
+		encoder
+			.encode(
+				Message::ping("ping"),
+				&mut buf,
+			)
+			.unwrap();
+		encoder
+			.encode(Message::text(text), &mut buf)
+			.unwrap();
+
+		assert_eq!(
+			decoder.decode(&mut buf).unwrap(),
+			Some(Message::text(text))
+		);
+		assert_eq!(
+			decoder.decode(&mut buf).unwrap(),
+			Some(Message::ping("ping"))
+		);
+		assert_eq!(
+			decoder.decode(&mut buf).unwrap(),
+			Some(Message::text(text))
+		);
+		assert!(buf.is_empty());
+	}
+
+	#[test]
+	fn decode_messages_ending_with_final_block() {
+		let mut decoder = MessageCodec::client().with_extension(negotiated(&[]));
+		let mut buf = BytesMut::new();
+		server_frame(
+			&mut buf,
+			true,
+			RSV1,
+			1,
+			&finished_stream(b"first message"),
+		);
+
+		let second = finished_stream(b"second message");
+		let (start, end) = second.split_at(second.len() / 2);
+		server_frame(
+			&mut buf, false, RSV1, 1, start,
+		);
+		server_frame(&mut buf, true, 0, 0, end);
+
+		assert_eq!(
+			decoder.decode(&mut buf).unwrap(),
+			Some(Message::text("first message"))
+		);
+		assert_eq!(
+			decoder.decode(&mut buf).unwrap(),
+			Some(Message::text(
+				"second message"
+			))
+		);
+	}
+
+	#[test]
+	fn reject_data_after_final_block() {
+		let mut decoder = MessageCodec::client().with_extension(negotiated(&[]));
+		let mut data = finished_stream(b"message");
+		data.extend_from_slice(&TRAILER);
+
+		let mut buf = BytesMut::new();
+		server_frame(&mut buf, true, RSV1, 1, &data);
+		assert!(matches!(
+			decoder.decode(&mut buf),
+			Err(Error::Protocol(
+				ProtocolError::InvalidCompressedData
+			))
+		));
+	}
+
+	#[test]
+	fn stop_inflating_at_max_message_size() {
+		let limit = 1 << 16;
+		let data = finished_stream(&vec![0; 100 * limit]);
+		assert!(data.len() < limit);
+
+		let mut buf = BytesMut::new();
+		server_frame(&mut buf, true, RSV1, 2, &data);
+
+		let mut decoder = MessageCodec::client()
+			.with_max_frame_size(limit)
+			.with_max_message_size(limit)
+			.with_extension(negotiated(&[]));
+
+		match decoder.decode(&mut buf.clone()) {
+			Err(Error::MessageTooLarge { size, limit: max }) => {
+				assert_eq!(max, limit as u64);
+				assert!(
+					size <= 4 * limit as u64,
+					"inflated {size} bytes"
+				);
+			}
+			result => panic!("expected MessageTooLarge, got {result:?}"),
+		}
+
+		let mut decoder = MessageCodec::client()
+			.with_max_message_size(limit)
+			.with_extension(negotiated(&[]));
+
+		assert!(matches!(
+			decoder.decode_fragment(&mut buf),
+			Err(Error::MessageTooLarge { .. })
+		));
+	}
+
+	#[test]
+	fn stop_inflating_across_fragments() {
+		let limit = 1 << 16;
+		let data = finished_stream(&vec![0; 3 * limit / 2]);
+		let (start, end) = data.split_at(data.len() / 2);
+
+		let mut buf = BytesMut::new();
+		server_frame(
+			&mut buf, false, RSV1, 2, start,
+		);
+		server_frame(&mut buf, true, 0, 0, end);
+
+		let mut decoder = MessageCodec::client()
+			.with_max_message_size(limit)
+			.with_extension(negotiated(&[]));
+
+		match decoder.decode(&mut buf) {
+			Err(Error::MessageTooLarge { size, limit: max }) => {
+				assert_eq!(max, limit as u64);
+				assert!(size > limit as u64);
+			}
+			result => panic!("expected MessageTooLarge, got {result:?}"),
+		}
+	}
+
+	#[test]
+	fn accept_window_bits() {
+		let mut config = DeflateConfig::new();
+		config.set_client_max_window_bits(10);
+		config.set_server_max_window_bits(12);
+
+		let mut extension = PerMessageDeflate::new(config);
+		extension
+			.accept(&[
+				(
+					"client_max_window_bits",
+					Some("10"),
+				),
+				(
+					"server_max_window_bits",
+					Some("8"),
+				),
+			])
+			.unwrap();
+	}
+
+	#[test]
+	fn reject_invalid_window_bits() {
+		for value in [None, Some("7"), Some("16"), Some("abc")] {
+			for name in ["client_max_window_bits", "server_max_window_bits"] {
+				let mut extension = PerMessageDeflate::new(DeflateConfig::new());
+				assert!(
+					matches!(
+						extension.accept(&[(name, value)]),
+						Err(Error::InvalidHandshake(_))
+					),
+					"{name}={value:?}"
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn reject_window_bits_larger_than_offered() {
+		let mut config = DeflateConfig::new();
+		config.set_client_max_window_bits(10);
+		config.set_server_max_window_bits(10);
+
+		for name in ["client_max_window_bits", "server_max_window_bits"] {
+			let mut extension = PerMessageDeflate::new(config.clone());
+			assert!(matches!(
+				extension.accept(&[(name, Some("11"))]),
+				Err(Error::InvalidHandshake(_))
+			));
+		}
+	}
+
+	#[test]
+	fn reject_unknown_and_repeated_parameters() {
+		for params in [
+			&[("unknown", None)][..],
+			&[(
+				"server_no_context_takeover",
+				Some("1"),
+			)],
+			&[
+				(
+					"server_no_context_takeover",
+					None,
+				),
+				(
+					"server_no_context_takeover",
+					None,
+				),
+			],
+		] {
+			let mut extension = PerMessageDeflate::new(DeflateConfig::new());
+			assert!(matches!(
+				extension.accept(params),
+				Err(Error::InvalidHandshake(_))
+			));
+		}
+	}
+
+	#[test]
+	fn reject_rsv1_on_continuation_frame() {
+		let mut decoder = MessageCodec::client().with_extension(negotiated(&[]));
+		let mut buf = BytesMut::new();
+		server_frame(&mut buf, false, 0, 2, b"abc");
+		server_frame(
+			&mut buf, true, RSV1, 0, b"def",
+		);
+		assert!(matches!(
+			decoder.decode(&mut buf),
+			Err(Error::Protocol(
+				ProtocolError::InvalidExtensionBits(_)
+			))
+		));
+	}
+
+	#[test]
+	fn reject_rsv1_on_control_frame() {
+		let mut decoder = MessageCodec::client().with_extension(negotiated(&[]));
+		let mut buf = BytesMut::new();
+		server_frame(
+			&mut buf, true, RSV1, 9, b"ping",
+		);
+		assert!(matches!(
+			decoder.decode(&mut buf),
+			Err(Error::Protocol(
+				ProtocolError::InvalidExtensionBits(_)
+			))
+		));
+	}
+}
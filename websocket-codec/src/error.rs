@@ -0,0 +1,238 @@
+use std::{
+	error,
+	fmt,
+	io,
+	str,
+};
+
+use crate::close::CloseCode;
+use crate::opcode::Opcode;
+use crate::upgrade::HandshakeError;
+
+/// Represents errors that can be exposed by this crate.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+	/// Reading from or writing to the underlying stream failed.
+	Io(io::Error),
+	/// The TLS library failed to set up or use a secure connection.
+	Tls(Box<dyn error::Error + Send + Sync + 'static>),
+	/// The server refused to upgrade the connection to the WebSocket protocol.
+	Handshake(HandshakeError),
+	/// The opening handshake was malformed or did not follow the WebSocket protocol.
+	InvalidHandshake(String),
+	/// The peer sent a frame that violates the WebSocket protocol.
+	Protocol(ProtocolError),
+	/// A text message or a close reason was not valid UTF-8.
+	Utf8(str::Utf8Error),
+	/// A frame or message was larger than this side is prepared to accept.
+	MessageTooLarge {
+		/// The size of the frame or message, in bytes.
+		size: u64,
+		/// The largest size that is accepted, in bytes.
+		limit: u64,
+	},
+	/// The URL is invalid or can't be used to connect.
+	Url(String),
+	/// An extension failed to transform a frame.
+	Extension(Box<dyn error::Error + Send + Sync + 'static>),
+	/// A message was sent after the closing handshake had started.
+	ConnectionClosed,
+	/// The server did not respond to a keepalive ping in time.
+	HeartbeatTimeout,
+	/// Establishing the connection took longer than the timeout set for one of its phases.
+	Timeout(ConnectPhase),
+}
+
+/// A phase of establishing a WebSocket connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ConnectPhase {
+	/// Looking up the addresses of the server's host name.
+	Dns,
+	/// Opening a TCP connection to the server.
+	Connect,
+	/// Performing the TLS handshake.
+	Tls,
+	/// Sending the HTTP upgrade request and waiting for the server's response.
+	Upgrade,
+}
+
+impl fmt::Display for ConnectPhase {
+	fn fmt(
+		&self,
+		f: &mut fmt::Formatter<'_>,
+	) -> fmt::Result {
+		f.write_str(match self {
+			Self::Dns => "DNS lookup",
+			Self::Connect => "TCP connect",
+			Self::Tls => "TLS handshake",
+			Self::Upgrade => "HTTP upgrade",
+		})
+	}
+}
+
+impl Error {
+	/// Returns the close code that should be sent to the peer when a connection fails with this error.
+	///
+	/// Returns `None` for errors that don't arise from data sent by the peer, such as I/O errors.
+	#[must_use]
+	pub fn close_code(&self) -> Option<CloseCode> {
+		match self {
+			Self::Protocol(err) => Some(err.close_code()),
+			Self::Utf8(_) => Some(CloseCode::Invalid),
+			Self::MessageTooLarge { .. } => Some(CloseCode::Size),
+			Self::Extension(_) => Some(CloseCode::Protocol),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(
+		&self,
+		f: &mut fmt::Formatter<'_>,
+	) -> fmt::Result {
+		match self {
+			Self::Io(err) => err.fmt(f),
+			Self::Tls(err) => write!(f, "TLS error: {err}"),
+			Self::Handshake(err) => err.fmt(f),
+			Self::InvalidHandshake(message) | Self::Url(message) => f.write_str(message),
+			Self::Protocol(err) => err.fmt(f),
+			Self::Utf8(err) => err.fmt(f),
+			Self::MessageTooLarge { size, limit } => {
+				write!(
+					f,
+					"message is too long: {size} bytes (limit is {limit} bytes)"
+				)
+			}
+			Self::Extension(err) => write!(f, "extension error: {err}"),
+			Self::ConnectionClosed => f.write_str("connection is closed"),
+			Self::HeartbeatTimeout => f.write_str("heartbeat timeout: server did not respond to ping"),
+			Self::Timeout(phase) => write!(f, "{phase} timed out"),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Self::Io(err) => Some(err),
+			Self::Tls(err) | Self::Extension(err) => Some(err.as_ref()),
+			Self::Handshake(err) => Some(err),
+			Self::Protocol(err) => Some(err),
+			Self::Utf8(err) => Some(err),
+			Self::InvalidHandshake(_)
+			| Self::MessageTooLarge { .. }
+			| Self::Url(_)
+			| Self::ConnectionClosed
+			| Self::HeartbeatTimeout
+			| Self::Timeout(_) => None,
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(err: io::Error) -> Self {
+		Self::Io(err)
+	}
+}
+
+impl From<HandshakeError> for Error {
+	fn from(err: HandshakeError) -> Self {
+		Self::Handshake(err)
+	}
+}
+
+impl From<ProtocolError> for Error {
+	fn from(err: ProtocolError) -> Self {
+		Self::Protocol(err)
+	}
+}
+
+impl From<str::Utf8Error> for Error {
+	fn from(err: str::Utf8Error) -> Self {
+		Self::Utf8(err)
+	}
+}
+
+/// Describes how the peer violated the WebSocket protocol.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ProtocolError {
+	/// A frame had RSV bits set that no negotiated extension uses.
+	ReservedBits(u8),
+	/// A frame had an opcode that is not defined by the WebSocket protocol.
+	UnsupportedOpcode(u8),
+	/// A frame's payload length was not encoded in the minimal number of bytes, or had its most
+	/// significant bit set.
+	InvalidLength(u64),
+	/// A control frame had a payload longer than 125 bytes.
+	ControlFrameTooLong(usize),
+	/// A control frame did not have the FIN bit set.
+	FragmentedControlFrame,
+	/// A data frame arrived while a fragmented message was in progress.
+	ExpectedContinuation(Opcode),
+	/// A continuation frame arrived without a fragmented message in progress.
+	UnexpectedContinuation,
+	/// A close frame had a payload of a single byte.
+	InvalidCloseFrame,
+	/// A frame's RSV bits were not valid for the negotiated extension.
+	InvalidExtensionBits(&'static str),
+	/// A compressed message could not be decompressed.
+	InvalidCompressedData,
+	/// A server received a frame from the client that was not masked.
+	UnmaskedFrame,
+	/// A client received a frame from the server that was masked.
+	MaskedFrame,
+}
+
+impl ProtocolError {
+	/// Returns the close code that should be sent to the peer that caused this error.
+	#[must_use]
+	pub fn close_code(&self) -> CloseCode {
+		match self {
+			Self::InvalidCompressedData => CloseCode::Invalid,
+			_ => CloseCode::Protocol,
+		}
+	}
+}
+
+impl fmt::Display for ProtocolError {
+	fn fmt(
+		&self,
+		f: &mut fmt::Formatter<'_>,
+	) -> fmt::Result {
+		match self {
+			Self::ReservedBits(rsv) => write!(
+				f,
+				"reserved bits are not supported: 0x{rsv:x}"
+			),
+			Self::UnsupportedOpcode(opcode) => write!(
+				f,
+				"opcode {opcode} is not supported"
+			),
+			Self::InvalidLength(len) => write!(
+				f,
+				"payload length {len} is not encoded correctly"
+			),
+			Self::ControlFrameTooLong(len) => write!(
+				f,
+				"control frames must be shorter than 126 bytes ({len} bytes is too long)"
+			),
+			Self::FragmentedControlFrame => f.write_str("control frames must not be fragmented"),
+			Self::ExpectedContinuation(opcode) => write!(
+				f,
+				"continuation frame must have continuation opcode, not {opcode:?}"
+			),
+			Self::UnexpectedContinuation => f.write_str("continuation must not be first frame"),
+			Self::InvalidCloseFrame => f.write_str("close frames must be at least 2 bytes long"),
+			Self::InvalidExtensionBits(message) => f.write_str(message),
+			Self::InvalidCompressedData => f.write_str("compressed message could not be decompressed"),
+			Self::UnmaskedFrame => f.write_str("frames sent by the client must be masked"),
+			Self::MaskedFrame => f.write_str("frames sent by the server must not be masked"),
+		}
+	}
+}
+
+impl error::Error for ProtocolError {}
@@ -0,0 +1,128 @@
+use bytes::{
+	Bytes,
+	BytesMut,
+};
+
+use crate::frame::FrameHeader;
+use crate::Result;
+
+/// An extension to the WebSocket protocol, negotiated through the `Sec-WebSocket-Extensions` header.
+///
+/// An extension offers itself during the opening handshake, accepts or rejects the parameters returned by the
+/// server, and can then claim RSV bits and transform the payload of every frame sent or received by the
+/// [`MessageCodec`](crate::MessageCodec).
+///
+/// Frames are passed through the extensions in the order that the server listed them when encoding, and in the
+/// reverse order when decoding. Control frames are passed through extensions as well as data frames.
+///
+/// # Example
+///
+/// An extension that inverts every bit of the data frames it sends, flagging them with RSV2:
+///
+/// ```
+/// use bytes::{Bytes, BytesMut};
+/// use tokio_util::codec::{Decoder, Encoder};
+/// use websocket_codec::{FrameHeader, Message, MessageCodec, Opcode, Result, WebSocketExtension};
+///
+/// const RSV2: u8 = 0x20;
+///
+/// struct Invert;
+///
+/// impl WebSocketExtension for Invert {
+///     fn name(&self) -> &str {
+///         "x-invert"
+///     }
+///
+///     fn offer(&self) -> String {
+///         "x-invert".to_owned()
+///     }
+///
+///     fn accept(&mut self, _params: &[(&str, Option<&str>)]) -> Result<()> {
+///         Ok(())
+///     }
+///
+///     fn rsv_bits(&self) -> u8 {
+///         RSV2
+///     }
+///
+///     fn encode_frame(&mut self, header: &mut FrameHeader, data: Bytes) -> Result<Bytes> {
+///         if Opcode::try_from(header.opcode()).is_some_and(Opcode::is_control) {
+///             return Ok(data);
+///         }
+///
+///         header.set_rsv(header.rsv() | RSV2);
+///         Ok(data.iter().map(|b| !b).collect())
+///     }
+///
+///     fn decode_frame(
+///         &mut self,
+///         header: &FrameHeader,
+///         mut data: BytesMut,
+///         _max_len: Option<usize>,
+///     ) -> Result<BytesMut> {
+///         if header.rsv() & RSV2 != 0 {
+///             data.iter_mut().for_each(|b| *b = !*b);
+///         }
+///
+///         Ok(data)
+///     }
+/// }
+///
+/// let mut client = MessageCodec::client().with_extension(Box::new(Invert));
+/// let mut server = MessageCodec::server().with_extension(Box::new(Invert));
+///
+/// let mut buf = BytesMut::new();
+/// client.encode(Message::text("hello"), &mut buf)?;
+/// assert_eq!(server.decode(&mut buf)?, Some(Message::text("hello")));
+/// # Ok::<(), websocket_codec::Error>(())
+/// ```
+pub trait WebSocketExtension: Send {
+	/// Returns the extension token, as it appears in the `Sec-WebSocket-Extensions` header.
+	fn name(&self) -> &str;
+
+	/// Returns the offer sent to the server, starting with the extension token and followed by any parameters.
+	fn offer(&self) -> String;
+
+	/// Applies the parameters that the server returned for this extension.
+	///
+	/// # Errors
+	///
+	/// Returning `Err` fails the opening handshake.
+	fn accept(
+		&mut self,
+		params: &[(&str, Option<&str>)],
+	) -> Result<()>;
+
+	/// Returns the RSV bits (a combination of `0x40`, `0x20` and `0x10`) that this extension may set on frames.
+	fn rsv_bits(&self) -> u8;
+
+	/// Transforms the payload of an outgoing frame.
+	///
+	/// The header describes the frame before the payload has been transformed; its length and mask are filled in
+	/// by the codec afterwards. Use [`FrameHeader::set_rsv`] to set the extension's RSV bits.
+	///
+	/// # Errors
+	///
+	/// Returning `Err` fails the encoding of the message.
+	fn encode_frame(
+		&mut self,
+		header: &mut FrameHeader,
+		data: Bytes,
+	) -> Result<Bytes>;
+
+	/// Transforms the payload of an incoming frame, after it has been unmasked.
+	///
+	/// `max_len` is how much data the frame may still add to its message under the codec's maximum message size.
+	/// Extensions that expand the payload should stop and return [`Error::MessageTooLarge`](crate::Error::MessageTooLarge)
+	/// as soon as their output exceeds it, rather than producing all of it first.
+	///
+	/// # Errors
+	///
+	/// Returning `Err` fails the decoding of the message.
+	fn decode_frame(
+		&mut self,
+		header: &FrameHeader,
+		data: BytesMut,
+		max_len: Option<usize>,
+	) -> Result<BytesMut>;
+}
@@ -0,0 +1,64 @@
+use bytes::Bytes;
+
+use crate::opcode::Opcode;
+
+/// One frame of a message that is sent or received piece by piece.
+///
+/// Every fragment carries the opcode of the message it belongs to, even though only the first frame of a message
+/// carries the opcode on the wire. A message that fits in a single frame is a fragment that is both first and last.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fragment {
+	opcode: Opcode,
+	data: Bytes,
+	first: bool,
+	last: bool,
+}
+
+impl Fragment {
+	/// Creates a fragment of a message with the given opcode.
+	///
+	/// The data in text fragments is not validated, since a UTF-8 sequence can be split across two fragments.
+	pub fn new<B: Into<Bytes>>(
+		opcode: Opcode,
+		data: B,
+		first: bool,
+		last: bool,
+	) -> Self {
+		Self {
+			opcode,
+			data: data.into(),
+			first,
+			last,
+		}
+	}
+
+	/// Returns the opcode of the message that this fragment belongs to.
+	#[must_use]
+	pub fn opcode(&self) -> Opcode {
+		self.opcode
+	}
+
+	/// Returns a reference to the data held in this fragment.
+	#[must_use]
+	pub fn data(&self) -> &Bytes {
+		&self.data
+	}
+
+	/// Consumes the fragment, returning its data.
+	#[must_use]
+	pub fn into_data(self) -> Bytes {
+		self.data
+	}
+
+	/// Returns `true` if this is the first fragment of its message.
+	#[must_use]
+	pub fn is_first(&self) -> bool {
+		self.first
+	}
+
+	/// Returns `true` if this is the last fragment of its message.
+	#[must_use]
+	pub fn is_last(&self) -> bool {
+		self.last
+	}
+}
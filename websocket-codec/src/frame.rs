@@ -1,8 +1,5 @@
 use std::convert::TryFrom;
-use std::{
-	mem,
-	usize,
-};
+use std::usize;
 
 use byteorder::{
 	BigEndian,
@@ -18,6 +15,7 @@ use tokio_util::codec::{
 use crate::mask::Mask;
 use crate::{
 	Error,
+	ProtocolError,
 	Result,
 };
 
@@ -54,29 +52,13 @@ impl TryFrom<DataLength> for u64 {
 			DataLength::Small(n) => Ok(u64::from(n)),
 			DataLength::Medium(n) => {
 				if n <= 125 {
-					return Err(format!(
-						"payload length {} should not be represented using 16 bits",
-						n
-					)
-					.into());
+					return Err(ProtocolError::InvalidLength(u64::from(n)).into());
 				}
 				Ok(u64::from(n))
 			}
 			DataLength::Large(n) => {
-				if n <= 65535 {
-					return Err(format!(
-						"payload length {} should not be represented using 64 bits",
-						n
-					)
-					.into());
-				}
-
-				if n >= 0x8000_0000_0000_0000 {
-					return Err(format!(
-						"frame is too long: {} bytes ({:x})",
-						n, n
-					)
-					.into());
+				if n <= 65535 || n >= 0x8000_0000_0000_0000 {
+					return Err(ProtocolError::InvalidLength(n).into());
 				}
 
 				Ok(n as u64)
@@ -98,12 +80,10 @@ impl TryFrom<DataLength> for usize {
 	fn try_from(len: DataLength) -> Result<Self> {
 		let len = u64::try_from(len)?;
 		if len > usize::MAX as u64 {
-			return Err(format!(
-				"frame of {} bytes can't be parsed on a {}-bit platform",
-				len,
-				mem::size_of::<usize>() / 8
-			)
-			.into());
+			return Err(Error::MessageTooLarge {
+				size: len,
+				limit: usize::MAX as u64,
+			});
 		}
 
 		Ok(len as usize)
@@ -150,12 +130,22 @@ impl FrameHeader {
 
 	/// Returns the WebSocket RSV1, RSV2 and RSV3 bits.
 	///
-	/// The RSV bits may be used by extensions to the WebSocket protocol not exposed by this crate.
+	/// The RSV bits are used by extensions to the WebSocket protocol.
 	#[must_use]
 	pub fn rsv(&self) -> u8 {
 		self.rsv
 	}
 
+	/// Sets the WebSocket RSV1, RSV2 and RSV3 bits.
+	///
+	/// This is used by a [`WebSocketExtension`](crate::WebSocketExtension) to flag the frames it has transformed.
+	pub fn set_rsv(
+		&mut self,
+		rsv: u8,
+	) {
+		self.rsv = rsv;
+	}
+
 	/// Returns the WebSocket opcode, which defines the interpretation of the frame payload data.
 	#[must_use]
 	pub fn opcode(&self) -> u8 {
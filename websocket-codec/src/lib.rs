@@ -7,6 +7,10 @@
 //! This crate does not do any I/O directly. For a full WebSocket client, see the [websocket-lite](https://docs.rs/websocket-lite) crate.
 
 mod close;
+mod deflate;
+mod error;
+mod extension;
+mod fragment;
 mod frame;
 mod mask;
 mod message;
@@ -19,6 +23,21 @@ pub use crate::close::{
 	CloseCode,
 	CloseFrame,
 };
+pub use crate::deflate::{
+	DeflateConfig,
+	PerMessageDeflate,
+};
+pub use crate::error::{
+	ConnectPhase,
+	Error,
+	ProtocolError,
+};
+pub use crate::extension::WebSocketExtension;
+pub use crate::fragment::Fragment;
+pub use crate::frame::{
+	DataLength,
+	FrameHeader,
+};
 pub use crate::message::{
 	Message,
 	MessageCodec,
@@ -26,16 +45,12 @@ pub use crate::message::{
 pub use crate::opcode::Opcode;
 pub use crate::upgrade::{
 	ClientRequest,
+	HandshakeError,
 	UpgradeCodec,
+	UpgradeResponse,
 };
 
-use std::{
-	error,
-	result,
-};
-
-/// Represents errors that can be exposed by this crate.
-pub type Error = Box<dyn error::Error + Send + Sync + 'static>;
+use std::result;
 
 /// Represents results returned by the non-async functions in this crate.
 pub type Result<T> = result::Result<T, Error>;
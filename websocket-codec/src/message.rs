@@ -2,12 +2,15 @@ use crate::close::{
 	CloseCode,
 	CloseFrame,
 };
+use crate::extension::WebSocketExtension;
+use crate::fragment::Fragment;
 use crate::frame::FrameHeader;
 use crate::mask::Mask;
 use crate::opcode::Opcode;
 use crate::{
 	mask,
 	Error,
+	ProtocolError,
 	Result,
 };
 use bytes::{
@@ -18,6 +21,7 @@ use bytes::{
 };
 use std::convert::TryFrom;
 use std::{
+	io,
 	str,
 	usize,
 };
@@ -53,7 +57,7 @@ impl Message {
 		match opcode {
 			Opcode::Close => match data.len() {
 				0 => {}
-				1 => return Err("close frames must be at least 2 bytes long".into()),
+				1 => return Err(ProtocolError::InvalidCloseFrame.into()),
 				_ => {
 					str::from_utf8(&data[2..])?;
 				}
@@ -83,19 +87,6 @@ impl Message {
 		}
 	}
 
-	pub(crate) fn header(
-		&self,
-		mask: Option<Mask>,
-	) -> FrameHeader {
-		FrameHeader {
-			fin: true,
-			rsv: 0,
-			opcode: self.opcode.into(),
-			mask,
-			data_len: self.data.len().into(),
-		}
-	}
-
 	/// Creates a message that indicates the connection is about to be closed.
 	/// The close frame does not contain a reason.
 	#[must_use]
@@ -189,10 +180,22 @@ impl Message {
 }
 
 /// Tokio codec for WebSocket messages. This codec can send and receive [`Message`] structs.
-#[derive(Clone)]
+///
+/// Large messages can also be sent piece by piece by encoding a series of [`Fragment`] structs.
+///
+/// The codec can't be cloned, since extensions such as `permessage-deflate` keep compression state that is tied to
+/// a single connection.
 pub struct MessageCodec {
 	interrupted_message: Option<(Opcode, BytesMut)>,
+	fragment_opcode: Option<Opcode>,
+	fragment_rsv: u8,
 	use_mask: bool,
+	lenient_masking: bool,
+	extensions: Vec<Box<dyn WebSocketExtension>>,
+	subprotocol: Option<String>,
+	max_frame_size: Option<usize>,
+	max_message_size: Option<usize>,
+	max_outgoing_frame_size: Option<usize>,
 }
 
 impl MessageCodec {
@@ -204,22 +207,396 @@ impl MessageCodec {
 		Self::with_masked_encode(true)
 	}
 
-	// /// Creates a `MessageCodec` for a server.
-	// ///
-	// /// Encoded messages are not masked.
-	// #[must_use]
-	// pub fn server() -> Self {
-	//     Self::with_masked_encode(false)
-	// }
+	/// Creates a `MessageCodec` for a server.
+	///
+	/// Encoded messages are not masked.
+	#[must_use]
+	pub fn server() -> Self {
+		Self::with_masked_encode(false)
+	}
 
 	/// Creates a `MessageCodec` while specifying whether to use message masking while encoding.
+	///
+	/// A codec that masks the messages it encodes acts as a client, and rejects masked frames when decoding. A codec
+	/// that doesn't acts as a server, and rejects unmasked frames.
 	#[must_use]
 	pub fn with_masked_encode(use_mask: bool) -> Self {
 		Self {
 			use_mask,
+			lenient_masking: false,
 			interrupted_message: None,
+			fragment_opcode: None,
+			fragment_rsv: 0,
+			extensions: Vec::new(),
+			subprotocol: None,
+			max_frame_size: None,
+			max_message_size: None,
+			max_outgoing_frame_size: None,
+		}
+	}
+
+	/// Limits the payload length of incoming frames, as declared in the frame header.
+	///
+	/// Decoding fails with [`Error::MessageTooLarge`] as soon as the header of a longer frame has been received,
+	/// before any space is reserved for its payload. By default, there is no limit.
+	#[must_use]
+	pub fn with_max_frame_size(
+		mut self,
+		size: usize,
+	) -> Self {
+		self.max_frame_size = Some(size);
+		self
+	}
+
+	/// Limits the length of incoming messages, after fragments have been reassembled and extensions such as
+	/// `permessage-deflate` have been applied.
+	///
+	/// Decoding fails with [`Error::MessageTooLarge`] as soon as the limit is exceeded, without decompressing the rest
+	/// of the frame. Frames that no extension transforms are rejected as soon as their header has been received, before
+	/// any space is reserved for their payload. By default, there is no limit.
+	#[must_use]
+	pub fn with_max_message_size(
+		mut self,
+		size: usize,
+	) -> Self {
+		self.max_message_size = Some(size);
+		self
+	}
+
+	/// Splits outgoing text and binary messages into frames carrying at most `size` bytes of payload each.
+	///
+	/// The limit applies to the payload before extensions such as `permessage-deflate` transform it. Control frames
+	/// are never split. By default, every message is sent as a single frame.
+	///
+	/// # Panics
+	///
+	/// Panics if `size` is zero.
+	#[must_use]
+	pub fn with_max_outgoing_frame_size(
+		mut self,
+		size: usize,
+	) -> Self {
+		assert!(
+			size > 0,
+			"frame size must be greater than zero"
+		);
+		self.max_outgoing_frame_size = Some(size);
+		self
+	}
+
+	/// Decodes a single frame, without reassembling fragmented messages.
+	///
+	/// Every fragment is tagged with the opcode of the message it belongs to. Control frames that arrive in the
+	/// middle of a fragmented message are returned as fragments that are both first and last. The maximum frame size
+	/// applies, and the maximum message size limits the data of each fragment once extensions have been applied,
+	/// since the fragments of a message aren't buffered. The data in text fragments is not validated, since a UTF-8
+	/// sequence can be split across two fragments.
+	///
+	/// Calls to this method shouldn't be mixed with calls to [`Decoder::decode`] in the middle of a fragmented
+	/// message.
+	///
+	/// # Errors
+	///
+	/// This method returns an `Err` result if the frame violates the WebSocket protocol or is too large.
+	pub fn decode_fragment(
+		&mut self,
+		src: &mut BytesMut,
+	) -> Result<Option<Fragment>> {
+		self.decode_fragment_after(src, 0)
+	}
+
+	/// Decodes a single frame belonging to a message of which `message_len` bytes have already been received.
+	fn decode_fragment_after(
+		&mut self,
+		src: &mut BytesMut,
+		message_len: usize,
+	) -> Result<Option<Fragment>> {
+		let Some((header, header_len)) = FrameHeader::parse_slice(src) else {
+			// The buffer isn't big enough for the frame header.
+			// Reserve additional space for a frame header, plus reasonable extensions.
+			src.reserve(512);
+			return Ok(None);
+		};
+
+		let data_len = usize::try_from(header.data_len)?;
+		self.check_frame_size(&header, data_len, message_len)?;
+
+		let frame_len = header_len + data_len;
+		if frame_len > src.remaining() {
+			// The buffer contains the frame header but it's not big enough for the data.
+			// Reserve additional space for the frame data, plus the next frame header.
+			// Note that we guard against bad data that indicates an unreasonable frame length.
+
+			// If we reserved buffer space for the entire frame data in a single call, would the buffer exceed usize::MAX bytes in size?
+			// On a 64-bit platform we should not reach here as the usize::try_from line above enforces the max payload length detailed in the RFC of 2^63 bytes.
+			if frame_len > usize::MAX - src.remaining() {
+				return Err(Error::MessageTooLarge {
+					size: frame_len as u64,
+					limit: usize::MAX as u64,
+				});
+			}
+
+			// We don't really reserve space for the entire frame data in a single call.
+			// If somebody is sending more than a gigabyte of data in a single frame then we'll still try to receive it, we'll just reserve in 1GB chunks.
+			src.reserve(frame_len.min(0x4000_0000) + 512);
+			return Ok(None);
+		}
+
+		// The buffer contains the frame header and all of the data. We can parse it and return Ok(Some(...)).
+		let mut data = src.split_to(frame_len);
+		data.advance(header_len);
+
+		let FrameHeader {
+			fin,
+			rsv,
+			opcode,
+			mask,
+			data_len: _data_len,
+		} = header;
+
+		if rsv & !self.allowed_rsv() != 0 {
+			return Err(ProtocolError::ReservedBits(rsv).into());
+		}
+
+		if !self.lenient_masking && mask.is_some() == self.use_mask {
+			return Err(if self.use_mask {
+				ProtocolError::MaskedFrame
+			} else {
+				ProtocolError::UnmaskedFrame
+			}
+			.into());
+		}
+
+		if let Some(mask) = mask {
+			mask::mask_slice(&mut data, mask);
+		};
+
+		let opcode = if opcode == 0 {
+			None
+		} else {
+			let opcode = Opcode::try_from(opcode).ok_or(ProtocolError::UnsupportedOpcode(opcode))?;
+			if opcode.is_control() && data_len >= 126 {
+				return Err(ProtocolError::ControlFrameTooLong(data_len).into());
+			}
+
+			Some(opcode)
+		};
+
+		// Control frames don't count towards the message they interrupt
+		let message_len = if opcode.is_some_and(Opcode::is_control) {
+			0
+		} else {
+			message_len
+		};
+
+		let data = self
+			.decode_extensions(&header, data, message_len)?
+			.freeze();
+		let fragment = match opcode {
+			Some(opcode) if opcode.is_control() => {
+				if !fin {
+					return Err(ProtocolError::FragmentedControlFrame.into());
+				}
+
+				// Validate close frames the same way as complete messages
+				Message::new(opcode, data.clone())?;
+				Fragment::new(opcode, data, true, true)
+			}
+			Some(opcode) => {
+				if self.fragment_opcode.is_some() {
+					return Err(ProtocolError::ExpectedContinuation(opcode).into());
+				}
+
+				if !fin {
+					self.fragment_opcode = Some(opcode);
+					self.fragment_rsv = rsv;
+				}
+
+				Fragment::new(opcode, data, true, fin)
+			}
+			None => {
+				let opcode = self
+					.fragment_opcode
+					.ok_or(ProtocolError::UnexpectedContinuation)?;
+
+				if fin {
+					self.fragment_opcode = None;
+				}
+
+				Fragment::new(opcode, data, false, fin)
+			}
+		};
+
+		Ok(Some(fragment))
+	}
+
+	/// Returns the RSV bits that the extensions may set on frames.
+	fn allowed_rsv(&self) -> u8 {
+		self.extensions.iter().fold(0, |bits, extension| {
+			bits | extension.rsv_bits()
+		})
+	}
+
+	/// Checks the payload length declared in the header of an incoming frame, before any space is reserved for the
+	/// payload.
+	fn check_frame_size(
+		&self,
+		header: &FrameHeader,
+		data_len: usize,
+		message_len: usize,
+	) -> Result<()> {
+		Self::check_size(data_len, self.max_frame_size)?;
+
+		// Continuation frames are transformed according to the RSV bits of the first frame in the message
+		let rsv = if header.opcode == 0 {
+			self.fragment_rsv
+		} else {
+			header.rsv
+		};
+
+		// A payload that no extension transforms can be measured against the message limit straight away
+		let is_control = Opcode::try_from(header.opcode).is_some_and(Opcode::is_control);
+		if !is_control && rsv & self.allowed_rsv() == 0 {
+			Self::check_size(
+				message_len.saturating_add(data_len),
+				self.max_message_size,
+			)?;
+		}
+
+		Ok(())
+	}
+
+	/// Runs the payload of an incoming frame through the extensions, making sure that the message it belongs to stays
+	/// within the maximum message size.
+	fn decode_extensions(
+		&mut self,
+		header: &FrameHeader,
+		mut data: BytesMut,
+		message_len: usize,
+	) -> Result<BytesMut> {
+		let max_len = self
+			.max_message_size
+			.map(|limit| limit.saturating_sub(message_len));
+
+		for extension in self.extensions.iter_mut().rev() {
+			data = extension
+				.decode_frame(header, data, max_len)
+				.map_err(|err| match err {
+					Error::MessageTooLarge { size, limit } => Error::MessageTooLarge {
+						size: size.saturating_add(message_len as u64),
+						limit: limit.saturating_add(message_len as u64),
+					},
+					err => err,
+				})?;
+		}
+
+		Self::check_size(
+			message_len.saturating_add(data.len()),
+			self.max_message_size,
+		)?;
+
+		Ok(data)
+	}
+
+	fn encode_frame(
+		&mut self,
+		opcode: u8,
+		fin: bool,
+		mut data: Bytes,
+		dst: &mut BytesMut,
+	) -> Result<()> {
+		let mut header = FrameHeader {
+			fin,
+			rsv: 0,
+			opcode,
+			mask: None,
+			data_len: data.len().into(),
+		};
+
+		for extension in &mut self.extensions {
+			data = extension.encode_frame(&mut header, data)?;
+		}
+
+		header.mask = if self.use_mask { Some(Mask::new()) } else { None };
+		header.data_len = data.len().into();
+		header.write_to_bytes(dst);
+
+		if let Some(mask) = header.mask {
+			let offset = dst.len();
+			dst.reserve(data.len());
+
+			unsafe {
+				dst.set_len(offset + data.len());
+			}
+
+			mask::mask_slice_copy(
+				&mut dst[offset..],
+				&data,
+				mask,
+			);
+		} else {
+			dst.put_slice(&data);
+		}
+
+		Ok(())
+	}
+
+	fn check_size(
+		size: usize,
+		limit: Option<usize>,
+	) -> Result<()> {
+		match limit {
+			Some(limit) if size > limit => Err(Error::MessageTooLarge {
+				size: size as u64,
+				limit: limit as u64,
+			}),
+			_ => Ok(()),
 		}
 	}
+
+	/// Sets whether incoming frames are accepted regardless of whether they are masked.
+	///
+	/// RFC 6455 requires a client to fail on masked frames from the server and a server to fail on unmasked frames
+	/// from the client, which is what happens by default. Lenient masking is meant for testing, for instance to
+	/// decode the frames produced by a codec of the same role.
+	#[must_use]
+	pub fn with_lenient_masking(
+		mut self,
+		lenient: bool,
+	) -> Self {
+		self.lenient_masking = lenient;
+		self
+	}
+
+	/// Runs frames through an extension that has been negotiated with the peer, for instance through
+	/// [`UpgradeCodec::take_extensions`](crate::UpgradeCodec::take_extensions).
+	///
+	/// Extensions are applied to outgoing frames in the order they are added, and to incoming frames in reverse.
+	#[must_use]
+	pub fn with_extension(
+		mut self,
+		extension: Box<dyn WebSocketExtension>,
+	) -> Self {
+		self.extensions.push(extension);
+		self
+	}
+
+	/// Records the subprotocol that was selected during the opening handshake, for instance through
+	/// [`UpgradeCodec::take_subprotocol`](crate::UpgradeCodec::take_subprotocol).
+	#[must_use]
+	pub fn with_subprotocol(
+		mut self,
+		subprotocol: String,
+	) -> Self {
+		self.subprotocol = Some(subprotocol);
+		self
+	}
+
+	/// Returns the subprotocol that was selected during the opening handshake, if any.
+	#[must_use]
+	pub fn subprotocol(&self) -> Option<&str> {
+		self.subprotocol.as_deref()
+	}
 }
 
 fn truncate_floor_char_boundary(
@@ -249,127 +626,47 @@ impl Decoder for MessageCodec {
 		&mut self,
 		src: &mut BytesMut,
 	) -> Result<Option<Message>> {
-		let mut state = self.interrupted_message.take();
-		let (opcode, data) = loop {
-			let (header, header_len) = if let Some(tuple) = FrameHeader::parse_slice(src) {
-				tuple
-			} else {
-				// The buffer isn't big enough for the frame header.
-				// Reserve additional space for a frame header, plus reasonable extensions.
-				src.reserve(512);
-				self.interrupted_message = state;
-				return Ok(None);
-			};
+		loop {
+			let message_len = self
+				.interrupted_message
+				.as_ref()
+				.map_or(0, |(_, data)| data.len());
 
-			let data_len = usize::try_from(header.data_len)?;
-			let frame_len = header_len + data_len;
-			if frame_len > src.remaining() {
-				// The buffer contains the frame header but it's not big enough for the data.
-				// Reserve additional space for the frame data, plus the next frame header.
-				// Note that we guard against bad data that indicates an unreasonable frame length.
-
-				// If we reserved buffer space for the entire frame data in a single call, would the buffer exceed usize::MAX bytes in size?
-				// On a 64-bit platform we should not reach here as the usize::try_from line above enforces the max payload length detailed in the RFC of 2^63 bytes.
-				if frame_len > usize::MAX - src.remaining() {
-					return Err(format!(
-						"frame is too long: {0} bytes ({0:x})",
-						frame_len
-					)
-					.into());
-				}
-
-				// We don't really reserve space for the entire frame data in a single call.
-				// If somebody is sending more than a gigabyte of data in a single frame then we'll still try to receive it, we'll just reserve in 1GB chunks.
-				src.reserve(frame_len.min(0x4000_0000) + 512);
-
-				self.interrupted_message = state;
+			let Some(fragment) = self.decode_fragment_after(src, message_len)? else {
 				return Ok(None);
-			}
-
-			// The buffer contains the frame header and all of the data. We can parse it and return Ok(Some(...)).
-			let mut data = src.split_to(frame_len);
-			data.advance(header_len);
-
-			let FrameHeader {
-				fin,
-				rsv,
-				opcode,
-				mask,
-				data_len: _data_len,
-			} = header;
-
-			if rsv != 0 {
-				return Err(format!(
-					"reserved bits are not supported: 0x{:x}",
-					rsv
-				)
-				.into());
-			}
-
-			if let Some(mask) = mask {
-				// Note: clients never need decode masked messages because masking is only used for client -> server frames.
-				// However this code is used to test round tripping of masked messages.
-				mask::mask_slice(&mut data, mask);
 			};
 
-			let opcode = if opcode == 0 {
-				None
-			} else {
-				let opcode = Opcode::try_from(opcode).ok_or_else(|| {
-					format!(
-						"opcode {} is not supported",
-						opcode
-					)
-				})?;
-				if opcode.is_control() && data_len >= 126 {
-					return Err(format!(
-						"control frames must be shorter than 126 bytes ({} bytes is too long)",
-						data_len
-					)
-					.into());
-				}
+			let (opcode, first, last) = (
+				fragment.opcode(),
+				fragment.is_first(),
+				fragment.is_last(),
+			);
 
-				Some(opcode)
-			};
+			let data = fragment.into_data();
+			if first && last {
+				return Ok(Some(Message::new(
+					opcode, data,
+				)?));
+			}
 
-			state = if let Some((partial_opcode, mut partial_data)) = state {
-				if let Some(opcode) = opcode {
-					if fin && opcode.is_control() {
-						self.interrupted_message = Some((partial_opcode, partial_data));
-						break (opcode, data);
-					}
-
-					return Err(format!(
-						"continuation frame must have continuation opcode, not {:?}",
-						opcode
-					)
-					.into());
-				}
+			let (_, partial_data) = self
+				.interrupted_message
+				.get_or_insert_with(|| (opcode, BytesMut::new()));
 
-				partial_data.extend_from_slice(&data);
+			partial_data.extend_from_slice(&data);
 
-				if fin {
-					break (partial_opcode, partial_data);
-				}
+			if last {
+				let (opcode, data) = self
+					.interrupted_message
+					.take()
+					.expect("message is being reassembled");
 
-				Some((partial_opcode, partial_data))
-			} else if let Some(opcode) = opcode {
-				if fin {
-					break (opcode, data);
-				}
-				if opcode.is_control() {
-					return Err("control frames must not be fragmented".into());
-				}
-				Some((opcode, data))
-			} else {
-				return Err("continuation must not be first frame".into());
+				return Ok(Some(Message::new(
+					opcode,
+					data.freeze(),
+				)?));
 			}
-		};
-
-		Ok(Some(Message::new(
-			opcode,
-			data.freeze(),
-		)?))
+		}
 	}
 }
 
@@ -393,27 +690,222 @@ impl<'a> Encoder<&'a Message> for MessageCodec {
 		item: &Message,
 		dst: &mut BytesMut,
 	) -> Result<()> {
-		let mask = if self.use_mask { Some(Mask::new()) } else { None };
-		let header = item.header(mask);
-		header.write_to_bytes(dst);
+		let mut data = item.data.clone();
+		let mut opcode = item.opcode.into();
+		if let Some(max_frame_size) = self
+			.max_outgoing_frame_size
+			.filter(|_| !item.opcode.is_control())
+		{
+			while data.len() > max_frame_size {
+				let chunk = data.split_to(max_frame_size);
+				self.encode_frame(opcode, false, chunk, dst)?;
+				opcode = 0;
+			}
+		}
 
-		if let Some(mask) = mask {
-			let offset = dst.len();
-			dst.reserve(item.data.len());
+		self.encode_frame(opcode, true, data, dst)
+	}
+}
 
-			unsafe {
-				dst.set_len(offset + item.data.len());
-			}
+impl Encoder<Fragment> for MessageCodec {
+	type Error = Error;
 
-			mask::mask_slice_copy(
-				&mut dst[offset..],
-				&item.data,
-				mask,
+	fn encode(
+		&mut self,
+		item: Fragment,
+		dst: &mut BytesMut,
+	) -> Result<()> {
+		let opcode = item.opcode();
+		let (first, last) = (
+			item.is_first(),
+			item.is_last(),
+		);
+		// A fragmented control frame is a mistake by the caller rather than by the peer
+		if opcode.is_control() && !(first && last) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"control frames can't be fragmented",
+			)
+			.into());
+		}
+
+		self.encode_frame(
+			if first { opcode.into() } else { 0 },
+			last,
+			item.into_data(),
+			dst,
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		DeflateConfig,
+		PerMessageDeflate,
+	};
+
+	/// Writes the header of an unmasked frame, as sent by a server, without reserving space for the payload.
+	fn server_header(
+		buf: &mut BytesMut,
+		fin: bool,
+		rsv: u8,
+		opcode: u8,
+		data_len: usize,
+	) {
+		let header = FrameHeader::new(
+			fin,
+			rsv,
+			opcode,
+			None,
+			data_len.into(),
+		);
+		let mut header_buf = [0; 14];
+		let header_len = header.header_len();
+		header.write_to_slice(&mut header_buf[..header_len]);
+		buf.put_slice(&header_buf[..header_len]);
+	}
+
+	#[test]
+	fn reject_large_frame_from_header() {
+		let mut codec = MessageCodec::client().with_max_message_size(10);
+		let mut buf = BytesMut::new();
+		server_header(&mut buf, true, 0, 2, 1 << 30);
+
+		assert!(matches!(
+			codec.decode(&mut buf),
+			Err(Error::MessageTooLarge {
+				size: 0x4000_0000,
+				limit: 10
+			})
+		));
+		assert!(buf.capacity() < 0x1000);
+	}
+
+	#[test]
+	fn reject_large_continuation_from_header() {
+		let mut codec = MessageCodec::client().with_max_message_size(10);
+		let mut buf = BytesMut::new();
+		server_header(&mut buf, false, 0, 1, 8);
+		buf.put_slice(b"12345678");
+		server_header(&mut buf, true, 0, 0, 1 << 30);
+
+		assert!(matches!(
+			codec.decode(&mut buf),
+			Err(Error::MessageTooLarge {
+				size: 0x4000_0008,
+				limit: 10
+			})
+		));
+		assert!(buf.capacity() < 0x1000);
+	}
+
+	#[test]
+	fn wait_for_compressed_frame_larger_than_limit() {
+		let mut extension = PerMessageDeflate::new(DeflateConfig::new());
+		extension.accept(&[]).unwrap();
+
+		// The payload may well inflate to less than it takes on the wire
+		let mut codec = MessageCodec::client()
+			.with_max_message_size(10)
+			.with_extension(Box::new(extension));
+		let mut buf = BytesMut::new();
+		server_header(&mut buf, true, 0x40, 2, 20);
+
+		assert!(matches!(
+			codec.decode(&mut buf),
+			Ok(None)
+		));
+	}
+
+	/// Encodes a text message with `encoder` and decodes it with `decoder`.
+	fn exchange(
+		encoder: &mut MessageCodec,
+		decoder: &mut MessageCodec,
+	) -> Result<Option<Message>> {
+		let mut buf = BytesMut::new();
+		encoder.encode(
+			Message::text("hello"),
+			&mut buf,
+		)?;
+		decoder.decode(&mut buf)
+	}
+
+	#[test]
+	fn client_rejects_masked_frame() {
+		assert!(matches!(
+			exchange(
+				&mut MessageCodec::client(),
+				&mut MessageCodec::client()
+			),
+			Err(Error::Protocol(
+				ProtocolError::MaskedFrame
+			))
+		));
+	}
+
+	#[test]
+	fn server_rejects_unmasked_frame() {
+		assert!(matches!(
+			exchange(
+				&mut MessageCodec::server(),
+				&mut MessageCodec::server()
+			),
+			Err(Error::Protocol(
+				ProtocolError::UnmaskedFrame
+			))
+		));
+	}
+
+	#[test]
+	fn lenient_masking_accepts_either() {
+		for (mut encoder, mut decoder) in [
+			(
+				MessageCodec::client(),
+				MessageCodec::client(),
+			),
+			(
+				MessageCodec::server(),
+				MessageCodec::server(),
+			),
+			(
+				MessageCodec::client(),
+				MessageCodec::server(),
+			),
+			(
+				MessageCodec::server(),
+				MessageCodec::client(),
+			),
+		] {
+			decoder = decoder.with_lenient_masking(true);
+			let message = exchange(&mut encoder, &mut decoder)
+				.unwrap()
+				.unwrap();
+			assert_eq!(
+				message.as_text(),
+				Some("hello")
 			);
-		} else {
-			dst.put_slice(&item.data);
 		}
+	}
 
-		Ok(())
+	#[test]
+	fn reject_fragmented_control_frame_locally() {
+		let mut buf = BytesMut::new();
+		let err = MessageCodec::client()
+			.encode(
+				Fragment::new(
+					Opcode::Ping,
+					Bytes::new(),
+					true,
+					false,
+				),
+				&mut buf,
+			)
+			.unwrap_err();
+
+		assert!(matches!(&err, Error::Io(err) if err.kind() == io::ErrorKind::InvalidInput));
+		assert_eq!(err.close_code(), None);
+		assert!(buf.is_empty());
 	}
 }
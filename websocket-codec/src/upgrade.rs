@@ -1,6 +1,9 @@
-use std::fmt::Write;
+use std::time::Duration;
 use std::{
-	result,
+	error,
+	fmt,
+	io,
+	mem,
 	str,
 };
 
@@ -20,6 +23,7 @@ use tokio_util::codec::{
 	Encoder,
 };
 
+use crate::extension::WebSocketExtension;
 use crate::{
 	Error,
 	Result,
@@ -37,77 +41,139 @@ fn build_ws_accept(key: &str) -> Sha1Digest {
 fn header<'a, 'header: 'a>(
 	headers: &'a [Header<'header>],
 	name: &'a str,
-) -> result::Result<&'header [u8], String> {
+) -> Result<&'header [u8]> {
 	let header = headers
 		.iter()
 		.find(|header| header.name.eq_ignore_ascii_case(name))
 		.ok_or_else(|| {
-			format!(
+			Error::InvalidHandshake(format!(
 				"server didn't respond with {name} header",
 				name = name
-			)
+			))
 		})?;
 
 	Ok(header.value)
 }
 
-fn validate_server_response(
-	expected_ws_accept: &Sha1Digest,
-	data: &[u8],
-) -> Result<Option<usize>> {
-	let mut headers = [httparse::EMPTY_HEADER; 20];
-	let mut response = Response::new(&mut headers);
-	let status = response.parse(data)?;
-	if !status.is_complete() {
+fn header_str<'a>(
+	name: &str,
+	value: &'a [u8],
+) -> Result<&'a str> {
+	str::from_utf8(value).map_err(|_| {
+		Error::InvalidHandshake(format!(
+			"server responded with non-UTF-8 {name} header"
+		))
+	})
+}
+
+type ExtensionParams<'a> = Vec<(&'a str, Option<&'a str>)>;
+
+/// Splits a `Sec-WebSocket-Extensions` header value into extension names and their parameters.
+fn parse_extensions(value: &str) -> Vec<(&str, ExtensionParams<'_>)> {
+	value
+		.split(',')
+		.filter(|extension| !extension.trim().is_empty())
+		.map(|extension| {
+			let mut parts = extension.split(';').map(str::trim);
+			let name = parts.next().unwrap_or_default();
+			let params = parts
+				.filter(|param| !param.is_empty())
+				.map(
+					|param| match param.split_once('=') {
+						Some((name, value)) => (
+							name.trim(),
+							Some(value.trim().trim_matches('"')),
+						),
+						None => (param, None),
+					},
+				)
+				.collect();
+
+			(name, params)
+		})
+		.collect()
+}
+
+fn negotiate_subprotocol(
+	headers: &[Header<'_>],
+	offered: &[String],
+) -> Result<Option<String>> {
+	let mut subprotocols = headers.iter().filter(|header| {
+		header
+			.name
+			.eq_ignore_ascii_case("Sec-WebSocket-Protocol")
+	});
+
+	let Some(header) = subprotocols.next() else {
 		return Ok(None);
+	};
+
+	if subprotocols.next().is_some() {
+		return Err(Error::InvalidHandshake(
+			"server responded with more than one Sec-WebSocket-Protocol header".to_owned(),
+		));
 	}
 
-	let response_len = status.unwrap();
-	let code = response.code.unwrap();
-	if code != 101 {
-		let mut error_message = format!(
-			"server responded with HTTP error {code}",
-			code = code
-		);
+	let subprotocol = header_str(
+		"Sec-WebSocket-Protocol",
+		header.value,
+	)?
+	.trim();
+	if !offered
+		.iter()
+		.any(|offered| offered == subprotocol)
+	{
+		return Err(Error::InvalidHandshake(
+			format!("server selected a subprotocol that was not offered: {subprotocol}"),
+		));
+	}
 
-		if let Some(reason) = response.reason {
-			write!(
-				error_message,
-				": {:?}",
-				reason
-			)
-			.expect("formatting reason failed");
-		}
-
-		return Err(error_message.into());
-	}
-
-	let ws_accept_header = header(
-		response.headers,
-		"Sec-WebSocket-Accept",
-	)?;
-	let mut ws_accept = Sha1Digest::default();
-	base64::engine::GeneralPurpose::decode_slice(
-		&base64::engine::general_purpose::STANDARD,
-		&ws_accept_header,
-		&mut ws_accept,
-	)?;
-	if expected_ws_accept != &ws_accept {
-		return Err(format!(
-			"server responded with incorrect Sec-WebSocket-Accept header: expected {expected}, got {actual}",
-			expected = Base64Display::new(
-				expected_ws_accept,
-				&base64::engine::general_purpose::STANDARD
-			),
-			actual = Base64Display::new(
-				&ws_accept,
-				&base64::engine::general_purpose::STANDARD
-			),
-		)
-		.into());
+	Ok(Some(subprotocol.to_owned()))
+}
+
+/// Returns the extensions that the server accepted, in the order the server listed them.
+fn negotiate_extensions(
+	headers: &[Header<'_>],
+	offered: &mut Vec<Box<dyn WebSocketExtension>>,
+) -> Result<Vec<Box<dyn WebSocketExtension>>> {
+	let mut accepted: Vec<Box<dyn WebSocketExtension>> = Vec::new();
+	let mut rsv_bits = 0;
+	for header in headers {
+		if !header
+			.name
+			.eq_ignore_ascii_case("Sec-WebSocket-Extensions")
+		{
+			continue;
+		}
+
+		for (name, params) in parse_extensions(header_str(
+			"Sec-WebSocket-Extensions",
+			header.value,
+		)?) {
+			let index = offered
+				.iter()
+				.position(|extension| extension.name().eq_ignore_ascii_case(name))
+				.ok_or_else(|| {
+					Error::InvalidHandshake(format!(
+						"server responded with unexpected extension: {name}"
+					))
+				})?;
+
+			let mut extension = offered.remove(index);
+			extension.accept(&params)?;
+
+			if extension.rsv_bits() & rsv_bits != 0 {
+				return Err(Error::InvalidHandshake(
+					format!("extension {name} uses RSV bits claimed by another extension"),
+				));
+			}
+
+			rsv_bits |= extension.rsv_bits();
+			accepted.push(extension);
+		}
 	}
 
-	Ok(Some(response_len))
+	Ok(accepted)
 }
 
 fn contains_ignore_ascii_case(
@@ -144,15 +210,21 @@ impl ClientRequest {
 	where
 		F: Fn(&'static str) -> Option<&'a str> + 'a,
 	{
-		let header = |name| header(name).ok_or_else(|| format!("client didn't provide {name} header",));
+		let header = |name| {
+			header(name).ok_or_else(|| {
+				Error::InvalidHandshake(format!(
+					"client didn't provide {name} header"
+				))
+			})
+		};
 
 		let check_header = |name, expected| {
 			let actual = header(name)?;
 			if actual.eq_ignore_ascii_case(expected) {
 				Ok(())
 			} else {
-				Err(format!(
-					"client provided incorrect {name} header: expected {expected}, got {actual}"
+				Err(Error::InvalidHandshake(
+					format!("client provided incorrect {name} header: expected {expected}, got {actual}"),
 				))
 			}
 		};
@@ -165,8 +237,10 @@ impl ClientRequest {
 			) {
 				Ok(())
 			} else {
-				Err(format!(
-					"client provided incorrect {name} header: expected string containing {expected}, got {actual}",
+				Err(Error::InvalidHandshake(
+					format!(
+						"client provided incorrect {name} header: expected string containing {expected}, got {actual}",
+					),
 				))
 			}
 		};
@@ -202,9 +276,213 @@ impl ClientRequest {
 	}
 }
 
+/// The server's response to the client's HTTP `Connection: Upgrade` request.
+#[derive(Clone, Debug)]
+pub struct UpgradeResponse {
+	code: u16,
+	reason: String,
+	headers: Vec<(String, Vec<u8>)>,
+}
+
+impl UpgradeResponse {
+	fn new(
+		code: u16,
+		response: &Response<'_, '_>,
+	) -> Self {
+		Self {
+			code,
+			reason: response.reason.unwrap_or_default().to_owned(),
+			headers: response
+				.headers
+				.iter()
+				.map(|header| {
+					(
+						header.name.to_owned(),
+						header.value.to_owned(),
+					)
+				})
+				.collect(),
+		}
+	}
+
+	/// Returns the HTTP status code.
+	#[must_use]
+	pub fn code(&self) -> u16 {
+		self.code
+	}
+
+	/// Returns the HTTP reason phrase.
+	#[must_use]
+	pub fn reason(&self) -> &str {
+		&self.reason
+	}
+
+	/// Returns all of the response headers, in the order the server sent them.
+	pub fn headers(&self) -> impl Iterator<Item = (&str, &[u8])> {
+		self.headers.iter().map(|(name, value)| {
+			(
+				name.as_str(),
+				value.as_slice(),
+			)
+		})
+	}
+
+	/// Returns the value of the first header with the given name, ignoring case.
+	#[must_use]
+	pub fn header(
+		&self,
+		name: &str,
+	) -> Option<&[u8]> {
+		self.headers()
+			.find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+			.map(|(_, value)| value)
+	}
+
+	/// Returns the values of every header with the given name, ignoring case.
+	///
+	/// Some headers, such as `Set-Cookie`, can be sent more than once.
+	pub fn header_all<'a>(
+		&'a self,
+		name: &'a str,
+	) -> impl Iterator<Item = &'a [u8]> + 'a {
+		self.headers()
+			.filter(move |(header_name, _)| header_name.eq_ignore_ascii_case(name))
+			.map(|(_, value)| value)
+	}
+}
+
+/// The maximum number of bytes of the response body kept by a [`HandshakeError`].
+const MAX_ERROR_BODY_LEN: usize = 4096;
+
+/// Returns up to `MAX_ERROR_BODY_LEN` bytes of the body of an HTTP error response,
+/// or `None` if more data is needed.
+fn error_body(
+	code: u16,
+	headers: &[Header<'_>],
+	data: &[u8],
+	eof: bool,
+) -> Option<Vec<u8>> {
+	if code < 200 || code == 204 || code == 304 {
+		return Some(Vec::new());
+	}
+
+	let complete = |body: &[u8], done: bool| {
+		(done || eof || body.len() >= MAX_ERROR_BODY_LEN).then(|| body[..body.len().min(MAX_ERROR_BODY_LEN)].to_vec())
+	};
+
+	let chunked = header(headers, "Transfer-Encoding").is_ok_and(|value| contains_ignore_ascii_case(value, b"chunked"));
+
+	if chunked {
+		let mut body = Vec::new();
+		let mut data = data;
+		while body.len() < MAX_ERROR_BODY_LEN {
+			let Ok(httparse::Status::Complete((offset, size))) = httparse::parse_chunk_size(data) else {
+				return complete(&body, false);
+			};
+
+			if size == 0 {
+				break;
+			}
+
+			let chunk = &data[offset..];
+			let size = usize::try_from(size)
+				.unwrap_or(usize::MAX)
+				.min(chunk.len());
+			body.extend_from_slice(&chunk[..size]);
+			data = chunk[size..]
+				.strip_prefix(b"\r\n")
+				.unwrap_or_default();
+		}
+
+		return complete(&body, true);
+	}
+
+	let content_length = header(headers, "Content-Length")
+		.ok()
+		.and_then(|value| str::from_utf8(value).ok())
+		.and_then(|value| value.trim().parse::<usize>().ok());
+
+	match content_length {
+		Some(len) => complete(
+			&data[..len.min(data.len())],
+			data.len() >= len,
+		),
+		// The body is delimited by the server closing the connection
+		None => complete(data, false),
+	}
+}
+
+/// The server refused to upgrade the connection to the WebSocket protocol.
+///
+/// This is returned as [`Error::Handshake`] when the server responds with an HTTP status other than
+/// `101 Switching Protocols`. It holds the status, the headers and up to 4KB of the response body.
+#[derive(Debug)]
+pub struct HandshakeError {
+	response: UpgradeResponse,
+	body: Vec<u8>,
+}
+
+impl HandshakeError {
+	/// Returns the HTTP status code.
+	#[must_use]
+	pub fn code(&self) -> u16 {
+		self.response.code()
+	}
+
+	/// Returns the HTTP reason phrase.
+	#[must_use]
+	pub fn reason(&self) -> &str {
+		self.response.reason()
+	}
+
+	/// Returns the server's response, including its headers.
+	#[must_use]
+	pub fn response(&self) -> &UpgradeResponse {
+		&self.response
+	}
+
+	/// Returns the start of the response body.
+	#[must_use]
+	pub fn body(&self) -> &[u8] {
+		&self.body
+	}
+
+	/// Returns the delay requested by the server's `Retry-After` header, when given as a number of seconds.
+	#[must_use]
+	pub fn retry_after(&self) -> Option<Duration> {
+		let value = self.response.header("Retry-After")?;
+		let seconds = str::from_utf8(value).ok()?.trim().parse().ok()?;
+		Some(Duration::from_secs(seconds))
+	}
+}
+
+impl fmt::Display for HandshakeError {
+	fn fmt(
+		&self,
+		f: &mut fmt::Formatter<'_>,
+	) -> fmt::Result {
+		write!(
+			f,
+			"server responded with HTTP error {code}",
+			code = self.code()
+		)?;
+
+		if !self.reason().is_empty() {
+			write!(f, ": {:?}", self.reason())?;
+		}
+
+		Ok(())
+	}
+}
+
+impl error::Error for HandshakeError {}
+
 /// Tokio decoder for parsing the server's response to the client's HTTP `Connection: Upgrade` request.
 pub struct UpgradeCodec {
 	ws_accept: Sha1Digest,
+	extensions: Vec<Box<dyn WebSocketExtension>>,
+	subprotocols: Vec<String>,
+	subprotocol: Option<String>,
 }
 
 impl UpgradeCodec {
@@ -215,25 +493,167 @@ impl UpgradeCodec {
 	pub fn new(key: &str) -> Self {
 		UpgradeCodec {
 			ws_accept: build_ws_accept(key),
+			extensions: Vec::new(),
+			subprotocols: Vec::new(),
+			subprotocol: None,
+		}
+	}
+
+	/// Expects the server to accept or decline an extension.
+	///
+	/// The extension's [`offer`](WebSocketExtension::offer) must have been sent in the client's
+	/// `Sec-WebSocket-Extensions` header.
+	#[must_use]
+	pub fn with_extension(
+		mut self,
+		extension: Box<dyn WebSocketExtension>,
+	) -> Self {
+		self.extensions.push(extension);
+		self
+	}
+
+	/// Returns the extensions that the server accepted, in the order that they should be applied.
+	///
+	/// Call this after the server's response has been decoded, and pass the result to
+	/// [`MessageCodec::with_extension`](crate::MessageCodec::with_extension).
+	pub fn take_extensions(&mut self) -> Vec<Box<dyn WebSocketExtension>> {
+		mem::take(&mut self.extensions)
+	}
+
+	/// Allows the server to select a subprotocol.
+	///
+	/// The subprotocol must have been sent in the client's `Sec-WebSocket-Protocol` header. The handshake fails
+	/// if the server selects a subprotocol that was not offered.
+	#[must_use]
+	pub fn with_subprotocol(
+		mut self,
+		subprotocol: String,
+	) -> Self {
+		self.subprotocols.push(subprotocol);
+		self
+	}
+
+	/// Returns the subprotocol selected by the server, if any.
+	///
+	/// Call this after the server's response has been decoded.
+	pub fn take_subprotocol(&mut self) -> Option<String> {
+		self.subprotocol.take()
+	}
+
+	fn validate_server_response(
+		&mut self,
+		data: &[u8],
+		eof: bool,
+	) -> Result<Option<(usize, UpgradeResponse)>> {
+		let mut headers = [httparse::EMPTY_HEADER; 64];
+		let mut response = Response::new(&mut headers);
+		let status = response.parse(data).map_err(|err| {
+			Error::InvalidHandshake(format!(
+				"server responded with invalid HTTP: {err}"
+			))
+		})?;
+		if !status.is_complete() {
+			return Ok(None);
+		}
+
+		let response_len = status.unwrap();
+		let code = response.code.unwrap();
+		if code != 101 {
+			let Some(body) = error_body(
+				code,
+				response.headers,
+				&data[response_len..],
+				eof,
+			) else {
+				return Ok(None);
+			};
+
+			return Err(HandshakeError {
+				response: UpgradeResponse::new(code, &response),
+				body,
+			}
+			.into());
+		}
+
+		let ws_accept_header = header(
+			response.headers,
+			"Sec-WebSocket-Accept",
+		)?;
+		let mut ws_accept = Sha1Digest::default();
+		base64::engine::GeneralPurpose::decode_slice(
+			&base64::engine::general_purpose::STANDARD,
+			&ws_accept_header,
+			&mut ws_accept,
+		)
+		.map_err(|err| {
+			Error::InvalidHandshake(format!(
+				"server responded with invalid Sec-WebSocket-Accept header: {err}"
+			))
+		})?;
+		if self.ws_accept != ws_accept {
+			return Err(Error::InvalidHandshake(
+				format!(
+					"server responded with incorrect Sec-WebSocket-Accept header: expected {expected}, got {actual}",
+					expected = Base64Display::new(
+						&self.ws_accept,
+						&base64::engine::general_purpose::STANDARD
+					),
+					actual = Base64Display::new(
+						&ws_accept,
+						&base64::engine::general_purpose::STANDARD
+					),
+				),
+			));
 		}
+
+		self.subprotocol = negotiate_subprotocol(
+			response.headers,
+			&self.subprotocols,
+		)?;
+		self.extensions = negotiate_extensions(
+			response.headers,
+			&mut self.extensions,
+		)?;
+		Ok(Some((
+			response_len,
+			UpgradeResponse::new(code, &response),
+		)))
 	}
 }
 
 impl Decoder for UpgradeCodec {
-	type Item = ();
+	type Item = UpgradeResponse;
 	type Error = Error;
 
 	fn decode(
 		&mut self,
 		src: &mut BytesMut,
-	) -> Result<Option<()>> {
-		if let Some(response_len) = validate_server_response(&self.ws_accept, src)? {
+	) -> Result<Option<UpgradeResponse>> {
+		if let Some((response_len, response)) = self.validate_server_response(src, false)? {
 			src.advance(response_len);
-			Ok(Some(()))
+			Ok(Some(response))
 		} else {
 			Ok(None)
 		}
 	}
+
+	fn decode_eof(
+		&mut self,
+		src: &mut BytesMut,
+	) -> Result<Option<UpgradeResponse>> {
+		if let Some((response_len, response)) = self.validate_server_response(src, true)? {
+			src.advance(response_len);
+			Ok(Some(response))
+		} else if src.is_empty() {
+			Ok(None)
+		} else {
+			Err(io::Error::new(
+				io::ErrorKind::UnexpectedEof,
+				"server closed the connection during the HTTP response",
+			)
+			.into())
+		}
+	}
 }
 
 impl Encoder<()> for UpgradeCodec {
@@ -247,3 +667,196 @@ impl Encoder<()> for UpgradeCodec {
 		unimplemented!()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The key and accept value from the example in RFC 6455 section 1.3.
+	const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+	const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+	fn switching_protocols(headers: &str) -> BytesMut {
+		format!(
+			"HTTP/1.1 101 Switching Protocols\r\n\
+			 Upgrade: websocket\r\n\
+			 Connection: Upgrade\r\n\
+			 Sec-WebSocket-Accept: {ACCEPT}\r\n\
+			 {headers}\r\n"
+		)
+		.as_str()
+		.into()
+	}
+
+	fn offering(subprotocols: &[&str]) -> UpgradeCodec {
+		subprotocols.iter().fold(
+			UpgradeCodec::new(KEY),
+			|codec, subprotocol| codec.with_subprotocol((*subprotocol).to_owned()),
+		)
+	}
+
+	#[test]
+	fn subprotocol_selected() {
+		let mut codec = offering(&["chat", "superchat"]);
+		let mut response = switching_protocols("sec-websocket-protocol: superchat\r\n");
+		codec.decode(&mut response).unwrap().unwrap();
+		assert!(response.is_empty());
+		assert_eq!(
+			codec.take_subprotocol().as_deref(),
+			Some("superchat")
+		);
+	}
+
+	#[test]
+	fn subprotocol_not_selected() {
+		let mut codec = offering(&["chat"]);
+		codec
+			.decode(&mut switching_protocols(""))
+			.unwrap()
+			.unwrap();
+		assert_eq!(codec.take_subprotocol(), None);
+	}
+
+	#[test]
+	fn subprotocol_not_offered() {
+		for subprotocols in [&[][..], &["chat"]] {
+			let mut codec = offering(subprotocols);
+			let err = codec
+				.decode(&mut switching_protocols(
+					"Sec-WebSocket-Protocol: superchat\r\n",
+				))
+				.unwrap_err();
+			assert!(
+				matches!(&err, Error::InvalidHandshake(message) if message.contains("not offered")),
+				"{err}"
+			);
+		}
+	}
+
+	#[test]
+	fn subprotocol_header_repeated() {
+		let mut codec = offering(&["chat", "superchat"]);
+		let err = codec
+			.decode(&mut switching_protocols(
+				"Sec-WebSocket-Protocol: chat\r\nSec-WebSocket-Protocol: superchat\r\n",
+			))
+			.unwrap_err();
+		assert!(
+			matches!(&err, Error::InvalidHandshake(message) if message.contains("more than one")),
+			"{err}"
+		);
+	}
+
+	/// Feeds an error response to a fresh codec, returning `None` if the codec waits for more of the body.
+	fn refusal(
+		response: &[u8],
+		eof: bool,
+	) -> Option<HandshakeError> {
+		let mut codec = UpgradeCodec::new(KEY);
+		let mut src = BytesMut::from(response);
+		let result = if eof {
+			codec.decode_eof(&mut src)
+		} else {
+			codec.decode(&mut src)
+		};
+
+		match result {
+			Ok(None) => None,
+			Ok(Some(_)) => panic!("handshake succeeded"),
+			Err(Error::Handshake(err)) => Some(err),
+			Err(err) => panic!("{err}"),
+		}
+	}
+
+	#[test]
+	fn error_body_without_content() {
+		for status in ["100 Continue", "204 No Content", "304 Not Modified"] {
+			let err = refusal(
+				format!("HTTP/1.1 {status}\r\n\r\nignored").as_bytes(),
+				false,
+			)
+			.unwrap();
+			assert_eq!(err.body(), b"");
+		}
+	}
+
+	#[test]
+	fn error_body_content_length() {
+		let response = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 6\r\n\r\ndenied";
+		assert!(refusal(
+			&response[..response.len() - 1],
+			false
+		)
+		.is_none());
+
+		let err = refusal(response, false).unwrap();
+		assert_eq!(err.code(), 403);
+		assert_eq!(err.reason(), "Forbidden");
+		assert_eq!(err.body(), b"denied");
+	}
+
+	#[test]
+	fn error_body_chunked() {
+		let response = b"HTTP/1.1 403 Forbidden\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nden\r\n3\r\nied\r\n0\r\n\r\n";
+		assert!(refusal(
+			&response[..response.len() - 5],
+			false
+		)
+		.is_none());
+
+		let err = refusal(response, false).unwrap();
+		assert_eq!(err.body(), b"denied");
+	}
+
+	#[test]
+	fn error_body_close_delimited() {
+		let response = b"HTTP/1.1 403 Forbidden\r\n\r\ndenied";
+		assert!(refusal(response, false).is_none());
+
+		let err = refusal(response, true).unwrap();
+		assert_eq!(err.body(), b"denied");
+	}
+
+	#[test]
+	fn error_body_capped() {
+		let body = "x".repeat(MAX_ERROR_BODY_LEN + 100);
+		let responses = [
+			format!("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 100000\r\n\r\n{body}"),
+			format!(
+				"HTTP/1.1 500 Internal Server Error\r\nTransfer-Encoding: chunked\r\n\r\n{len:x}\r\n{body}",
+				len = 100_000
+			),
+			format!("HTTP/1.1 500 Internal Server Error\r\n\r\n{body}"),
+		];
+
+		for response in responses {
+			let err = refusal(response.as_bytes(), false).unwrap();
+			assert_eq!(
+				err.body(),
+				&body.as_bytes()[..MAX_ERROR_BODY_LEN]
+			);
+		}
+	}
+
+	#[test]
+	fn retry_after() {
+		let retry_after = |header: &str| {
+			refusal(
+				format!("HTTP/1.1 503 Service Unavailable\r\n{header}Content-Length: 0\r\n\r\n").as_bytes(),
+				false,
+			)
+			.unwrap()
+			.retry_after()
+		};
+
+		assert_eq!(
+			retry_after("Retry-After: 90\r\n"),
+			Some(Duration::from_secs(90))
+		);
+		assert_eq!(
+			retry_after("Retry-After: Wed, 21 Oct 2015 07:28:00 GMT\r\n"),
+			None
+		);
+		assert_eq!(retry_after(""), None);
+	}
+}